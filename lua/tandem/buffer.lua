@@ -132,7 +132,7 @@ local function on_lines(_event, bufnr, _changedtick, firstline, lastline, new_la
 
 	-- Apply to CRDT: delete old_byte_len bytes at start_byte, insert new_text
 	local end_byte = start_byte + old_byte_len
-	ffi.crdt.doc_apply_edit(state.doc_id, start_byte, end_byte, new_text)
+	ffi.crdt.doc_apply_edit(state.doc_id, "", start_byte, end_byte, new_text)
 
 	-- Notify session about the edit (for debouncing)
 	if on_edit_callback then
@@ -273,14 +273,14 @@ function M.poll_and_apply(bufnr)
 
 	-- Clear any pending deltas (we don't use them - we do full sync)
 	-- This is important to drain the queue so it doesn't grow unbounded
-	local delta_jsons = ffi.crdt.doc_poll_deltas(state.doc_id)
+	local delta_jsons = ffi.crdt.doc_poll_deltas(state.doc_id, "")
 	if #delta_jsons == 0 then
 		-- No remote changes happened
 		return 0
 	end
 
 	-- Remote changes happened - compare buffer with CRDT and sync if different
-	local crdt_content = ffi.crdt.doc_get_text(state.doc_id)
+	local crdt_content = ffi.crdt.doc_get_text(state.doc_id, "")
 	local buf_content = M.get_content(bufnr)
 
 	if crdt_content == buf_content then
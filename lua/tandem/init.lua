@@ -40,6 +40,10 @@ end
 M.config = {
 	debug = false,
 	poll_interval_ms = 50,
+	-- Rust log file; nil uses $TANDEM_LOG_PATH or tandem-nvim.log in the temp dir
+	log_file = nil,
+	-- Host relay: "" for the public iroh relays, "disabled" for LAN-only, or a relay URL
+	relay = "",
 }
 
 -- FFI module (loaded on setup)
@@ -66,11 +70,28 @@ function M.setup(opts)
 	-- Load FFI
 	M.ffi = load_ffi()
 
+	-- Configure Rust logging (debug mode logs everything, else $TANDEM_LOG_LEVEL or info)
+	if M.ffi then
+		M.ffi.set_log_config(M.config.log_file or "", M.config.debug and "debug" or "")
+	end
+
+	-- Tear down every connection and document on exit so no runtime tasks leak
+	vim.api.nvim_create_autocmd("VimLeavePre", {
+		group = vim.api.nvim_create_augroup("TandemTeardown", { clear = true }),
+		callback = function()
+			if M.ffi then
+				M.ffi.iroh.close_all()
+				M.ffi.crdt.doc_destroy_all()
+			end
+		end,
+	})
+
 	-- Configure session module
 	local sess = get_session()
 	sess.setup({
 		poll_interval_ms = M.config.poll_interval_ms,
 		debug = M.config.debug,
+		relay = M.config.relay,
 	})
 
 	if M.config.debug then
@@ -48,6 +48,7 @@ local config = {
 	poll_interval_ms = 50, -- How often to poll for updates
 	edit_debounce_ms = 100, -- Wait after last edit before sending
 	debug = false,
+	relay = "", -- Host relay: "" for default, "disabled" for LAN-only, or a relay URL
 }
 
 -- Seed RNG with time + PID for uniqueness across Neovim instances
@@ -87,7 +88,7 @@ local function sync_buffer_from_crdt()
 		return
 	end
 
-	local crdt_content = session.ffi.crdt.doc_get_text(session.doc_id)
+	local crdt_content = session.ffi.crdt.doc_get_text(session.doc_id, "")
 	local buf_content = buffer.get_content(session.bufnr)
 
 	if crdt_content ~= buf_content then
@@ -158,10 +159,14 @@ local function register_callbacks(client_id)
 	_G["_TANDEM_NVIM"].iroh = _G["_TANDEM_NVIM"].iroh or { callbacks = {} }
 
 	_G["_TANDEM_NVIM"].iroh.callbacks[client_id] = {
-		on_ready = function(_id, endpoint_id, relay_url)
+		on_ready = function(_id, endpoint_id, relay_url, direct_addrs)
 			log("INFO", "P2P endpoint ready: " .. endpoint_id)
 
 			local ok, code = pcall(function()
+				-- Embed direct addresses so joiners on the same LAN skip the relay
+				if direct_addrs and #direct_addrs > 0 then
+					return session.ffi.code.encode_with_addrs(endpoint_id, { relay_url }, direct_addrs)
+				end
 				return session.ffi.code.encode(endpoint_id, relay_url)
 			end)
 			if ok then
@@ -174,8 +179,8 @@ local function register_callbacks(client_id)
 			end
 		end,
 
-		on_peer_connected = function(_id, peer_id)
-			log("INFO", "Peer connected: " .. peer_id)
+		on_peer_connected = function(_id, peer_id, protocol_version)
+			log("INFO", "Peer connected: " .. peer_id .. " (protocol v" .. tostring(protocol_version) .. ")")
 			session.connected = true
 			session.peers[peer_id] = true
 
@@ -226,7 +231,7 @@ local function register_callbacks(client_id)
 				return
 			end
 
-			local crdt_content = session.ffi.crdt.doc_get_text(session.doc_id)
+			local crdt_content = session.ffi.crdt.doc_get_text(session.doc_id, "")
 			session.sync_lockout_until = vim.uv.now() + 100
 			buffer.set_content(session.bufnr, crdt_content)
 			session.last_sent_sv = session.ffi.crdt.doc_state_vector(session.doc_id)
@@ -263,7 +268,7 @@ local function register_callbacks(client_id)
 			end
 
 			-- Update buffer from CRDT
-			local crdt_content = session.ffi.crdt.doc_get_text(session.doc_id)
+			local crdt_content = session.ffi.crdt.doc_get_text(session.doc_id, "")
 			local buf_content = buffer.get_content(session.bufnr)
 			if crdt_content ~= buf_content then
 				session.ffi.crdt.doc_clear_deltas(session.doc_id)
@@ -359,7 +364,7 @@ local function poll_loop()
 		if session.integrity_check_counter >= 20 then
 			session.integrity_check_counter = 0
 
-			local crdt_text = session.ffi.crdt.doc_get_text(session.doc_id)
+			local crdt_text = session.ffi.crdt.doc_get_text(session.doc_id, "")
 			local buf_text = buffer.get_content(session.bufnr)
 
 			if crdt_text ~= buf_text then
@@ -408,7 +413,7 @@ function M.host(ffi_ref)
 	-- Initialize CRDT from buffer
 	local buf_content = buffer.get_content(session.bufnr)
 	if buf_content and buf_content ~= "" and buf_content ~= "\n" then
-		session.ffi.crdt.doc_set_text(session.doc_id, buf_content)
+		session.ffi.crdt.doc_set_text(session.doc_id, "", buf_content)
 		log("INFO", "Initialized CRDT from buffer (" .. #buf_content .. " bytes)")
 	end
 
@@ -429,7 +434,7 @@ function M.host(ffi_ref)
 	register_callbacks(session.iroh_client_id)
 
 	-- Start hosting
-	local ok = session.ffi.iroh.host(session.iroh_client_id)
+	local ok = session.ffi.iroh.host_with_relay(session.iroh_client_id, config.relay)
 	if not ok then
 		log("ERROR", "Failed to start P2P host")
 		unregister_callbacks(session.iroh_client_id)
@@ -636,6 +641,9 @@ function M.setup(opts)
 	if opts.debug ~= nil then
 		config.debug = opts.debug
 	end
+	if opts.relay ~= nil then
+		config.relay = opts.relay
+	end
 end
 
 return M
@@ -50,7 +50,7 @@ local function main()
 	-- Verify CRDT got the content
 	-- Note: Neovim internally treats buffer as "hello\n" (with trailing newline)
 	-- so we accept either "hello" or "hello\n"
-	local crdt_text = ffi.crdt.doc_get_text(doc_id)
+	local crdt_text = ffi.crdt.doc_get_text(doc_id, "")
 	local crdt_text_trimmed = crdt_text:gsub("\n$", "")
 	if crdt_text_trimmed ~= "hello" then
 		print("[FAIL] After set_lines, CRDT expected 'hello', got '" .. crdt_text:gsub("\n", "\\n") .. "'")
@@ -62,7 +62,7 @@ local function main()
 	vim.api.nvim_buf_set_text(bufnr, 0, 5, 0, 5, { " world" })
 	vim.wait(10)
 
-	crdt_text = ffi.crdt.doc_get_text(doc_id)
+	crdt_text = ffi.crdt.doc_get_text(doc_id, "")
 	crdt_text_trimmed = crdt_text:gsub("\n$", "")
 	if crdt_text_trimmed ~= "hello world" then
 		print("[FAIL] After append, CRDT expected 'hello world', got '" .. crdt_text:gsub("\n", "\\n") .. "'")
@@ -74,7 +74,7 @@ local function main()
 	vim.api.nvim_buf_set_text(bufnr, 0, 0, 0, 5, { "hi" })
 	vim.wait(10)
 
-	crdt_text = ffi.crdt.doc_get_text(doc_id)
+	crdt_text = ffi.crdt.doc_get_text(doc_id, "")
 	crdt_text_trimmed = crdt_text:gsub("\n$", "")
 	if crdt_text_trimmed ~= "hi world" then
 		print("[FAIL] After replace, CRDT expected 'hi world', got '" .. crdt_text:gsub("\n", "\\n") .. "'")
@@ -86,7 +86,7 @@ local function main()
 	vim.api.nvim_buf_set_lines(bufnr, 0, -1, true, { "line1", "line2", "line3" })
 	vim.wait(10)
 
-	crdt_text = ffi.crdt.doc_get_text(doc_id)
+	crdt_text = ffi.crdt.doc_get_text(doc_id, "")
 	crdt_text_trimmed = crdt_text:gsub("\n$", "")
 	if crdt_text_trimmed ~= "line1\nline2\nline3" then
 		print("[FAIL] Multi-line, CRDT expected 'line1\\nline2\\nline3', got '" .. crdt_text:gsub("\n", "\\n") .. "'")
@@ -98,7 +98,7 @@ local function main()
 	vim.api.nvim_buf_set_text(bufnr, 1, 0, 1, 5, { "LINE2" })
 	vim.wait(10)
 
-	crdt_text = ffi.crdt.doc_get_text(doc_id)
+	crdt_text = ffi.crdt.doc_get_text(doc_id, "")
 	crdt_text_trimmed = crdt_text:gsub("\n$", "")
 	if crdt_text_trimmed ~= "line1\nLINE2\nline3" then
 		print(
@@ -113,7 +113,7 @@ local function main()
 	-- Test 8: Simulate remote change
 	-- Create a second CRDT doc, make changes, sync to first
 	local doc_id2 = ffi.crdt.doc_create()
-	ffi.crdt.doc_set_text(doc_id2, "REMOTE")
+	ffi.crdt.doc_set_text(doc_id2, "", "REMOTE")
 
 	-- Get update from doc2
 	local sv1 = ffi.crdt.doc_state_vector(doc_id)
@@ -30,8 +30,8 @@ local function main()
 	print("[OK] doc_create: " .. doc_id)
 
 	-- Test 2: Set initial text
-	crdt.doc_set_text(doc_id, "hello")
-	local text = crdt.doc_get_text(doc_id)
+	crdt.doc_set_text(doc_id, "", "hello")
+	local text = crdt.doc_get_text(doc_id, "")
 	if text ~= "hello" then
 		print("[FAIL] doc_get_text expected 'hello', got '" .. text .. "'")
 		return
@@ -39,8 +39,8 @@ local function main()
 	print("[OK] doc_set_text/doc_get_text: 'hello'")
 
 	-- Test 3: Apply edit - append " world"
-	crdt.doc_apply_edit(doc_id, 5, 5, " world")
-	text = crdt.doc_get_text(doc_id)
+	crdt.doc_apply_edit(doc_id, "", 5, 5, " world")
+	text = crdt.doc_get_text(doc_id, "")
 	if text ~= "hello world" then
 		print("[FAIL] After append, expected 'hello world', got '" .. text .. "'")
 		return
@@ -48,8 +48,8 @@ local function main()
 	print("[OK] doc_apply_edit (append): 'hello world'")
 
 	-- Test 4: Apply edit - replace "hello" with "hi"
-	crdt.doc_apply_edit(doc_id, 0, 5, "hi")
-	text = crdt.doc_get_text(doc_id)
+	crdt.doc_apply_edit(doc_id, "", 0, 5, "hi")
+	text = crdt.doc_get_text(doc_id, "")
 	if text ~= "hi world" then
 		print("[FAIL] After replace, expected 'hi world', got '" .. text .. "'")
 		return
@@ -57,8 +57,8 @@ local function main()
 	print("[OK] doc_apply_edit (replace): 'hi world'")
 
 	-- Test 5: Apply edit - delete " world"
-	crdt.doc_apply_edit(doc_id, 2, 8, "")
-	text = crdt.doc_get_text(doc_id)
+	crdt.doc_apply_edit(doc_id, "", 2, 8, "")
+	text = crdt.doc_get_text(doc_id, "")
 	if text ~= "hi" then
 		print("[FAIL] After delete, expected 'hi', got '" .. text .. "'")
 		return
@@ -102,7 +102,7 @@ local function main()
 	print("[OK] Applied update to doc2")
 
 	-- Verify doc2 content matches doc1
-	local text2 = crdt.doc_get_text(doc_id2)
+	local text2 = crdt.doc_get_text(doc_id2, "")
 	if text2 ~= "hi" then
 		print("[FAIL] After sync, doc2 expected 'hi', got '" .. text2 .. "'")
 		return
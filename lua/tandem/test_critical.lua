@@ -28,7 +28,7 @@ local function main()
 		vim.api.nvim_buf_set_lines(bufnr, 0, -1, true, { "hello", "world" })
 		vim.wait(10)
 
-		local crdt_text = ffi.crdt.doc_get_text(doc_id):gsub("\n$", "")
+		local crdt_text = ffi.crdt.doc_get_text(doc_id, ""):gsub("\n$", "")
 		if crdt_text ~= "hello\nworld" then
 			print("[FAIL] Expected 'hello\\nworld', got '" .. crdt_text:gsub("\n", "\\n") .. "'")
 			return
@@ -39,7 +39,7 @@ local function main()
 		vim.api.nvim_buf_set_text(bufnr, 1, 0, 1, 5, { "WORLD" })
 		vim.wait(10)
 
-		crdt_text = ffi.crdt.doc_get_text(doc_id):gsub("\n$", "")
+		crdt_text = ffi.crdt.doc_get_text(doc_id, ""):gsub("\n$", "")
 		if crdt_text ~= "hello\nWORLD" then
 			print("[FAIL] After line 2 edit, expected 'hello\\nWORLD', got '" .. crdt_text:gsub("\n", "\\n") .. "'")
 			return
@@ -63,7 +63,7 @@ local function main()
 		vim.wait(10)
 
 		-- Verify basic content
-		local crdt_text = ffi.crdt.doc_get_text(doc_id):gsub("\n$", "")
+		local crdt_text = ffi.crdt.doc_get_text(doc_id, ""):gsub("\n$", "")
 		if crdt_text ~= "hello\nworld" then
 			print("[FAIL] Setup failed")
 			return
@@ -73,7 +73,7 @@ local function main()
 		vim.api.nvim_buf_set_text(bufnr, 0, 5, 0, 5, { " 😀" })
 		vim.wait(10)
 
-		crdt_text = ffi.crdt.doc_get_text(doc_id):gsub("\n$", "")
+		crdt_text = ffi.crdt.doc_get_text(doc_id, ""):gsub("\n$", "")
 		local expected = "hello 😀\nworld"
 		if crdt_text ~= expected then
 			print("[FAIL] After emoji insert, expected '" .. expected:gsub("\n", "\\n") .. "'")
@@ -86,7 +86,7 @@ local function main()
 		vim.api.nvim_buf_set_text(bufnr, 1, 0, 1, 5, { "WORLD" })
 		vim.wait(10)
 
-		crdt_text = ffi.crdt.doc_get_text(doc_id):gsub("\n$", "")
+		crdt_text = ffi.crdt.doc_get_text(doc_id, ""):gsub("\n$", "")
 		expected = "hello 😀\nWORLD"
 		if crdt_text ~= expected then
 			print("[FAIL] After edit on line after emoji, expected '" .. expected:gsub("\n", "\\n") .. "'")
@@ -111,7 +111,7 @@ local function main()
 		vim.wait(10)
 
 		-- The CRDT should have the edit
-		local crdt_text = ffi.crdt.doc_get_text(doc_id):gsub("\n$", "")
+		local crdt_text = ffi.crdt.doc_get_text(doc_id, ""):gsub("\n$", "")
 		if crdt_text ~= "test" then
 			print("[FAIL] Local edit not synced to CRDT")
 			return
@@ -147,7 +147,7 @@ local function main()
 		-- 2. Setting different content
 		-- 3. Syncing to our doc
 		local doc_id2 = ffi.crdt.doc_create()
-		ffi.crdt.doc_set_text(doc_id2, "REMOTE")
+		ffi.crdt.doc_set_text(doc_id2, "", "REMOTE")
 
 		local sv1 = ffi.crdt.doc_state_vector(doc_id)
 		local diff = ffi.crdt.doc_encode_update(doc_id2, sv1)
@@ -197,7 +197,7 @@ local function main()
 		end
 		vim.wait(50)
 
-		local crdt_text = ffi.crdt.doc_get_text(doc_id)
+		local crdt_text = ffi.crdt.doc_get_text(doc_id, "")
 		local buf_text = table.concat(vim.api.nvim_buf_get_lines(bufnr, 0, -1, true), "\n")
 
 		-- CRDT should roughly match buffer (accounting for trailing newline)
@@ -4,7 +4,9 @@
 //! clients generate their own JWTs. The server validates structure only, not signature.
 
 use chrono::{Duration, Utc};
-use jsonwebtoken::{EncodingKey, Header, encode};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
 use nvim_oxi::{Dictionary, Function, Object};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -22,40 +24,196 @@ pub struct Claims {
     pub sid: String,
 }
 
+/// Default token lifetime used by `generate_token` (24 hours).
+pub const DEFAULT_TTL_SECONDS: i64 = 24 * 3600;
+
 /// Generate a JWT token for anonymous authentication.
 ///
 /// The token is signed with a random secret since anonymous mode servers
 /// only validate the JWT structure, not the signature.
 pub fn generate_token(username: &str) -> String {
+    generate_token_with(username, DEFAULT_TTL_SECONDS, "").expect("failed to encode JWT")
+}
+
+/// Generate a JWT token with a custom lifetime and extra claims.
+///
+/// # Arguments
+/// * `username` - Subject (`sub`) of the token
+/// * `ttl_seconds` - Lifetime in seconds; must be positive
+/// * `extra_claims_json` - JSON object merged into the payload, or empty for none.
+///   The standard claims (`sub`, `iat`, `exp`, `sid`) cannot be overridden.
+pub fn generate_token_with(
+    username: &str,
+    ttl_seconds: i64,
+    extra_claims_json: &str,
+) -> Result<String, String> {
+    let payload = build_claims(username, ttl_seconds, extra_claims_json)?;
+
+    // Use a random secret - anonymous mode servers don't verify signatures
+    let secret = Uuid::new_v4().to_string();
+
+    encode(
+        &Header::default(),
+        &payload,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| format!("Failed to encode JWT: {e}"))
+}
+
+/// Generate a JWT signed with a shared secret for servers that verify signatures.
+///
+/// # Arguments
+/// * `username` - Subject (`sub`) of the token
+/// * `secret` - HMAC secret shared with the server
+/// * `algorithm` - One of "HS256", "HS384", "HS512"
+pub fn generate_token_signed(
+    username: &str,
+    secret: &str,
+    algorithm: &str,
+) -> Result<String, String> {
+    let alg = match algorithm {
+        "HS256" => Algorithm::HS256,
+        "HS384" => Algorithm::HS384,
+        "HS512" => Algorithm::HS512,
+        other => return Err(format!("Unsupported algorithm: {other}")),
+    };
+
+    if secret.is_empty() {
+        return Err("Secret cannot be empty".to_string());
+    }
+
+    let payload = build_claims(username, DEFAULT_TTL_SECONDS, "")?;
+
+    encode(
+        &Header::new(alg),
+        &payload,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .map_err(|e| format!("Failed to encode JWT: {e}"))
+}
+
+/// Build the JWT payload: standard `Claims` plus any extra claims.
+fn build_claims(
+    username: &str,
+    ttl_seconds: i64,
+    extra_claims_json: &str,
+) -> Result<serde_json::Map<String, serde_json::Value>, String> {
+    if ttl_seconds <= 0 {
+        return Err(format!("TTL must be positive, got {ttl_seconds}"));
+    }
+
     let now = Utc::now();
     let session_id = Uuid::new_v4().to_string();
+    let exp = Duration::try_seconds(ttl_seconds)
+        .and_then(|ttl| now.checked_add_signed(ttl))
+        .ok_or_else(|| format!("TTL too large: {ttl_seconds}"))?;
 
     let claims = Claims {
         sub: username.to_string(),
         iat: now.timestamp() as usize,
-        exp: (now + Duration::hours(24)).timestamp() as usize,
+        exp: exp.timestamp() as usize,
         sid: session_id,
     };
 
-    // Use a random secret - anonymous mode servers don't verify signatures
-    let secret = Uuid::new_v4().to_string();
+    let mut payload = match serde_json::to_value(&claims) {
+        Ok(serde_json::Value::Object(map)) => map,
+        Ok(_) => return Err("Claims did not serialize to an object".to_string()),
+        Err(e) => return Err(format!("Failed to serialize claims: {e}")),
+    };
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
+    if !extra_claims_json.is_empty() {
+        let extra: serde_json::Map<String, serde_json::Value> =
+            serde_json::from_str(extra_claims_json)
+                .map_err(|e| format!("Invalid extra claims (expected JSON object): {e}"))?;
+        for (key, value) in extra {
+            if payload.contains_key(&key) {
+                return Err(format!("Extra claim cannot override standard claim: {key}"));
+            }
+            payload.insert(key, value);
+        }
+    }
+
+    Ok(payload)
+}
+
+/// Verify a JWT signed with an HMAC secret and return its claims as JSON.
+///
+/// # Arguments
+/// * `token` - Encoded JWT
+/// * `secret` - Shared HMAC secret the token was signed with
+/// * `skip_exp` - Skip expiry checking (useful for inspecting old tokens)
+///
+/// # Returns
+/// The decoded `Claims` serialized as a JSON string
+pub fn verify_token(token: &str, secret: &str, skip_exp: bool) -> Result<String, String> {
+    let header = decode_header(token).map_err(|e| format!("Invalid token header: {e}"))?;
+
+    if !matches!(
+        header.alg,
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512
+    ) {
+        return Err(format!("Unsupported algorithm: {:?}", header.alg));
+    }
+
+    let mut validation = Validation::new(header.alg);
+    validation.validate_exp = !skip_exp;
+    if skip_exp {
+        validation.required_spec_claims.clear();
+    }
+
+    let data = decode::<Claims>(
+        token,
+        &DecodingKey::from_secret(secret.as_bytes()),
+        &validation,
     )
-    .expect("failed to encode JWT")
+    .map_err(|e| format!("Token verification failed: {e}"))?;
+
+    serde_json::to_string(&data.claims).map_err(|e| format!("Failed to serialize claims: {e}"))
 }
 
 /// Export auth functions to Lua via nvim-oxi.
 pub fn auth_ffi() -> Dictionary {
-    Dictionary::from_iter([(
-        "generate_token",
-        Object::from(Function::<String, String>::from_fn(
-            |username| -> Result<String, nvim_oxi::Error> { Ok(generate_token(&username)) },
-        )),
-    )])
+    Dictionary::from_iter([
+        (
+            "generate_token",
+            Object::from(Function::<String, String>::from_fn(
+                |username| -> Result<String, nvim_oxi::Error> { Ok(generate_token(&username)) },
+            )),
+        ),
+        (
+            "generate_token_signed",
+            Object::from(Function::<(String, String, String), String>::from_fn(
+                |(username, secret, algorithm)| -> Result<String, nvim_oxi::Error> {
+                    match generate_token_signed(&username, &secret, &algorithm) {
+                        Ok(token) => Ok(token),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "verify_token",
+            Object::from(Function::<(String, String, bool), String>::from_fn(
+                |(token, secret, skip_exp)| -> Result<String, nvim_oxi::Error> {
+                    match verify_token(&token, &secret, skip_exp) {
+                        Ok(claims) => Ok(claims),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "generate_token_with",
+            Object::from(Function::<(String, i64, String), String>::from_fn(
+                |(username, ttl_seconds, extra_claims)| -> Result<String, nvim_oxi::Error> {
+                    match generate_token_with(&username, ttl_seconds, &extra_claims) {
+                        Ok(token) => Ok(token),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+    ])
 }
 
 #[cfg(test)]
@@ -116,4 +274,104 @@ mod tests {
         assert!(claims.exp > now + 23 * 3600);
         assert!(claims.exp <= now + 25 * 3600);
     }
+
+    #[test]
+    fn test_generate_token_with_ttl_and_extra_claims() {
+        let token = generate_token_with("user", 300, r#"{"room":"abc","role":"editor"}"#)
+            .expect("generate");
+
+        let parts: Vec<&str> = token.split('.').collect();
+        let payload = URL_SAFE_NO_PAD.decode(parts[1]).unwrap();
+        let value: serde_json::Value = serde_json::from_slice(&payload).unwrap();
+        let claims: Claims = serde_json::from_value(value.clone()).unwrap();
+
+        assert_eq!(claims.sub, "user");
+        assert_eq!(claims.exp - claims.iat, 300);
+        assert_eq!(value["room"], "abc");
+        assert_eq!(value["role"], "editor");
+    }
+
+    #[test]
+    fn test_generate_token_with_rejects_bad_input() {
+        assert!(generate_token_with("user", 0, "").is_err());
+        assert!(generate_token_with("user", -60, "").is_err());
+        assert!(generate_token_with("user", 100_000_000_000_000_000, "").is_err());
+        assert!(generate_token_with("user", i64::MAX, "").is_err());
+        assert!(generate_token_with("user", 60, "[1, 2]").is_err());
+        assert!(generate_token_with("user", 60, r#"{"sub":"admin"}"#).is_err());
+    }
+
+    fn signed_token(sub: &str, exp_offset_secs: i64, secret: &str) -> String {
+        let now = Utc::now();
+        let claims = Claims {
+            sub: sub.to_string(),
+            iat: now.timestamp() as usize,
+            exp: (now + Duration::seconds(exp_offset_secs)).timestamp() as usize,
+            sid: Uuid::new_v4().to_string(),
+        };
+        encode(
+            &Header::default(),
+            &claims,
+            &EncodingKey::from_secret(secret.as_bytes()),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verify_token_valid() {
+        let token = signed_token("alice", 3600, "secret");
+
+        let json = verify_token(&token, "secret", false).expect("verify");
+        let claims: Claims = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(claims.sub, "alice");
+    }
+
+    #[test]
+    fn test_verify_token_expired() {
+        // Well past the default validation leeway
+        let token = signed_token("alice", -3600, "secret");
+
+        let result = verify_token(&token, "secret", false);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("verification failed"));
+
+        // Inspection mode still decodes it
+        let json = verify_token(&token, "secret", true).expect("verify skipping exp");
+        let claims: Claims = serde_json::from_str(&json).unwrap();
+        assert_eq!(claims.sub, "alice");
+    }
+
+    #[test]
+    fn test_verify_token_wrong_secret() {
+        let token = signed_token("alice", 3600, "secret");
+
+        assert!(verify_token(&token, "other-secret", false).is_err());
+        assert!(verify_token(&token, "other-secret", true).is_err());
+    }
+
+    #[test]
+    fn test_generate_token_signed_algorithms() {
+        for (name, alg) in [
+            ("HS256", Algorithm::HS256),
+            ("HS384", Algorithm::HS384),
+            ("HS512", Algorithm::HS512),
+        ] {
+            let token = generate_token_signed("bob", "secret", name).expect("generate");
+
+            assert_eq!(decode_header(&token).unwrap().alg, alg);
+            let json = verify_token(&token, "secret", false).expect("verify");
+            let claims: Claims = serde_json::from_str(&json).unwrap();
+            assert_eq!(claims.sub, "bob");
+        }
+    }
+
+    #[test]
+    fn test_generate_token_signed_rejects_bad_input() {
+        let result = generate_token_signed("bob", "secret", "RS256");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Unsupported algorithm"));
+
+        assert!(generate_token_signed("bob", "", "HS256").is_err());
+    }
 }
@@ -1,52 +1,280 @@
 //! P2P session code encoding/decoding.
 //!
-//! Format: `base64url(endpoint_id_str || 0x01 || relay_url)`
+//! Format: `base64url(version || endpoint_id_str || 0x01 || relay_urls || checksum)`
+//! - version: format version byte (currently 0x02)
 //! - endpoint_id_str: Iroh EndpointId as string (z32 encoded public key)
-//! - relay_url: URL of the relay server for NAT traversal
+//! - relay_urls: one or more relay server URLs for NAT traversal, 0x02-separated
+//! - checksum: first 2 bytes of SHA-256 over everything before it, to catch
+//!   codes mangled by copy-paste
+//!
+//! Codes may also carry the host's direct socket addresses for LAN
+//! connections (`encode_with_addrs`) as `0x04 || addr_1 || 0x02 || addr_2 ...`
+//! after the relay URLs.
+//!
+//! Key-bearing codes (`encode_with_key`) use version 0x03 and append
+//! `0x03 || key_b64` after the relay URLs, before the checksum, so the joiner
+//! gets endpoint, relays and E2E key from a single string.
+//!
+//! Version 0x01 codes (no checksum) and legacy codes without a version byte
+//! (starting directly with the printable endpoint ID) are still accepted.
 
 use base64ct::{Base64UrlUnpadded, Encoding};
 use nvim_oxi::{Dictionary, Function, Object};
+use sha2::{Digest, Sha256};
 
 /// Separator byte for P2P format
 const P2P_SEPARATOR: u8 = 0x01;
 
+/// Separator byte between relay URLs
+const RELAY_SEPARATOR: u8 = 0x02;
+
+/// Session code format version without checksum
+const VERSION_V1: u8 = 0x01;
+
+/// Current session code format version (with checksum)
+const VERSION_V2: u8 = 0x02;
+
+/// Session code format version carrying an encryption key (with checksum)
+const VERSION_V3_KEY: u8 = 0x03;
+
+/// Separator byte before the embedded encryption key
+const KEY_SEPARATOR: u8 = 0x03;
+
+/// Separator byte before the direct socket addresses
+const ADDRS_SEPARATOR: u8 = 0x04;
+
+/// Number of checksum bytes appended to v2 codes
+const CHECKSUM_SIZE: usize = 2;
+
+/// Truncated SHA-256 of `data`, used as the v2 checksum.
+fn checksum(data: &[u8]) -> [u8; CHECKSUM_SIZE] {
+    let digest = Sha256::digest(data);
+    [digest[0], digest[1]]
+}
+
 /// Encode EndpointId and RelayUrl into a P2P session code.
 ///
-/// Format: `base64url(endpoint_id_str || 0x01 || relay_url)`
+/// Format: `base64url(version || endpoint_id_str || 0x01 || relay_url || checksum)`
 pub fn encode(endpoint_id: &str, relay_url: &str) -> Result<String, String> {
-    // Validate inputs don't contain the separator
-    if endpoint_id.as_bytes().contains(&P2P_SEPARATOR) {
+    encode_relays(endpoint_id, &[relay_url])
+}
+
+/// Encode EndpointId and several RelayUrls into a P2P session code.
+///
+/// Format: `base64url(version || endpoint_id_str || 0x01 || relay_1 || 0x02 || relay_2 ... || checksum)`
+pub fn encode_relays<S: AsRef<str>>(endpoint_id: &str, relay_urls: &[S]) -> Result<String, String> {
+    encode_parts(endpoint_id, relay_urls, &[] as &[&str], None)
+}
+
+/// Encode EndpointId, RelayUrls and direct socket addresses into a P2P session code.
+///
+/// Format: `base64url(version || endpoint_id_str || 0x01 || relays || 0x04 || addr_1 || 0x02 || addr_2 ... || checksum)`
+pub fn encode_with_addrs<S: AsRef<str>, A: AsRef<str>>(
+    endpoint_id: &str,
+    relay_urls: &[S],
+    direct_addrs: &[A],
+) -> Result<String, String> {
+    for addr in direct_addrs {
+        addr.as_ref()
+            .parse::<std::net::SocketAddr>()
+            .map_err(|e| format!("Invalid direct address {}: {e}", addr.as_ref()))?;
+    }
+
+    encode_parts(endpoint_id, relay_urls, direct_addrs, None)
+}
+
+/// Encode EndpointId, RelayUrl and a base64url E2E key into one session code.
+///
+/// Format: `base64url(0x03 || endpoint_id_str || 0x01 || relay_url || 0x03 || key_b64 || checksum)`
+pub fn encode_with_key(
+    endpoint_id: &str,
+    relay_url: &str,
+    key_b64: &str,
+) -> Result<String, String> {
+    let key_bytes =
+        Base64UrlUnpadded::decode_vec(key_b64).map_err(|e| format!("Invalid key base64: {e}"))?;
+    if key_bytes.len() != crate::crypto::KEY_SIZE {
+        return Err(format!(
+            "Invalid key size: expected {}, got {}",
+            crate::crypto::KEY_SIZE,
+            key_bytes.len()
+        ));
+    }
+
+    encode_parts(endpoint_id, &[relay_url], &[] as &[&str], Some(key_b64))
+}
+
+fn encode_parts<S: AsRef<str>, A: AsRef<str>>(
+    endpoint_id: &str,
+    relay_urls: &[S],
+    direct_addrs: &[A],
+    key_b64: Option<&str>,
+) -> Result<String, String> {
+    // Validate inputs don't contain the separators
+    if endpoint_id.bytes().any(|b| b <= ADDRS_SEPARATOR) {
         return Err("Endpoint ID cannot contain separator byte".to_string());
     }
+    if relay_urls
+        .iter()
+        .any(|url| url.as_ref().bytes().any(|b| b <= ADDRS_SEPARATOR))
+    {
+        return Err("Relay URL cannot contain separator byte".to_string());
+    }
 
-    // Build payload: endpoint_id || 0x01 || relay_url
-    let mut payload = Vec::with_capacity(endpoint_id.len() + 1 + relay_url.len());
+    // Build payload:
+    // version || endpoint_id || 0x01 || relay_urls [|| 0x04 || addrs] [|| 0x03 || key] || checksum
+    let relays_len: usize = relay_urls.iter().map(|url| url.as_ref().len() + 1).sum();
+    let addrs_len: usize = direct_addrs.iter().map(|a| a.as_ref().len() + 1).sum();
+    let key_len = key_b64.map_or(0, |key| key.len() + 1);
+    let mut payload = Vec::with_capacity(
+        1 + endpoint_id.len() + 1 + relays_len + addrs_len + key_len + CHECKSUM_SIZE,
+    );
+    payload.push(if key_b64.is_some() {
+        VERSION_V3_KEY
+    } else {
+        VERSION_V2
+    });
     payload.extend_from_slice(endpoint_id.as_bytes());
     payload.push(P2P_SEPARATOR);
-    payload.extend_from_slice(relay_url.as_bytes());
+    for (i, url) in relay_urls.iter().enumerate() {
+        if i > 0 {
+            payload.push(RELAY_SEPARATOR);
+        }
+        payload.extend_from_slice(url.as_ref().as_bytes());
+    }
+    if !direct_addrs.is_empty() {
+        payload.push(ADDRS_SEPARATOR);
+        for (i, addr) in direct_addrs.iter().enumerate() {
+            if i > 0 {
+                payload.push(RELAY_SEPARATOR);
+            }
+            payload.extend_from_slice(addr.as_ref().as_bytes());
+        }
+    }
+    if let Some(key) = key_b64 {
+        payload.push(KEY_SEPARATOR);
+        payload.extend_from_slice(key.as_bytes());
+    }
+    let sum = checksum(&payload);
+    payload.extend_from_slice(&sum);
 
     Ok(Base64UrlUnpadded::encode_string(&payload))
 }
 
 /// Decode a P2P session code into (endpoint_id, relay_url).
+///
+/// For codes carrying several relays, only the first is returned; use
+/// `decode_relays` to get all of them.
 pub fn decode(code: &str) -> Result<(String, String), String> {
-    let payload =
+    let (endpoint_id, relay_urls) = decode_relays(code)?;
+    let relay_url = relay_urls.into_iter().next().unwrap_or_default();
+    Ok((endpoint_id, relay_url))
+}
+
+/// Decode a P2P session code into (endpoint_id, relay_urls), preserving order.
+pub fn decode_relays(code: &str) -> Result<(String, Vec<String>), String> {
+    let parts = decode_parts(code)?;
+    Ok((parts.endpoint_id, parts.relay_urls))
+}
+
+/// Decode a P2P session code into (endpoint_id, relay_urls, direct_addrs).
+///
+/// `direct_addrs` is empty for codes that don't carry any.
+pub fn decode_addrs(code: &str) -> Result<(String, Vec<String>, Vec<String>), String> {
+    let parts = decode_parts(code)?;
+    Ok((parts.endpoint_id, parts.relay_urls, parts.direct_addrs))
+}
+
+/// Decode a P2P session code into (endpoint_id, relay_url, key_b64).
+///
+/// `key_b64` is empty for codes that don't carry an encryption key.
+pub fn decode_with_key(code: &str) -> Result<(String, String, String), String> {
+    let parts = decode_parts(code)?;
+    let relay_url = parts.relay_urls.into_iter().next().unwrap_or_default();
+    Ok((parts.endpoint_id, relay_url, parts.key.unwrap_or_default()))
+}
+
+/// All fields a session code can carry.
+struct SessionCodeParts {
+    endpoint_id: String,
+    relay_urls: Vec<String>,
+    direct_addrs: Vec<String>,
+    key: Option<String>,
+}
+
+/// Split a 0x02-separated section into UTF-8 strings, skipping empty entries.
+fn split_list(section: &[u8], what: &str) -> Result<Vec<String>, String> {
+    section
+        .split(|&b| b == RELAY_SEPARATOR)
+        .filter(|item| !item.is_empty())
+        .map(|item| String::from_utf8(item.to_vec()).map_err(|e| format!("Invalid {what}: {e}")))
+        .collect()
+}
+
+fn decode_parts(code: &str) -> Result<SessionCodeParts, String> {
+    let data =
         Base64UrlUnpadded::decode_vec(code).map_err(|e| format!("Invalid session code: {e}"))?;
 
+    let payload = match data.first() {
+        Some(&(VERSION_V2 | VERSION_V3_KEY)) => {
+            if data.len() < 1 + CHECKSUM_SIZE {
+                return Err("Session code corrupted (checksum mismatch)".to_string());
+            }
+            let (body, sum) = data.split_at(data.len() - CHECKSUM_SIZE);
+            if checksum(body) != sum {
+                return Err("Session code corrupted (checksum mismatch)".to_string());
+            }
+            &body[1..]
+        }
+        Some(&VERSION_V1) => &data[1..],
+        // Legacy unversioned code: starts with a printable endpoint ID
+        Some(&b) if b.is_ascii_graphic() => &data[..],
+        Some(&b) => return Err(format!("Unsupported session code version: {b:#04x}")),
+        None => return Err("Invalid session code: empty".to_string()),
+    };
+
+    // Split off the key section for key-bearing codes
+    let (payload, key) = if data[0] == VERSION_V3_KEY {
+        let key_pos = payload
+            .iter()
+            .rposition(|&b| b == KEY_SEPARATOR)
+            .ok_or("Invalid session code: missing key separator")?;
+        let key = String::from_utf8(payload[key_pos + 1..].to_vec())
+            .map_err(|e| format!("Invalid key: {e}"))?;
+        (&payload[..key_pos], Some(key))
+    } else {
+        (payload, None)
+    };
+
     // Find separator
     let sep_pos = payload
         .iter()
         .position(|&b| b == P2P_SEPARATOR)
         .ok_or("Invalid session code: missing separator")?;
 
-    // Extract endpoint_id and relay_url
+    // Extract endpoint_id and relay_urls
     let endpoint_id = String::from_utf8(payload[..sep_pos].to_vec())
         .map_err(|e| format!("Invalid endpoint ID: {e}"))?;
 
-    let relay_url = String::from_utf8(payload[sep_pos + 1..].to_vec())
-        .map_err(|e| format!("Invalid relay URL: {e}"))?;
+    // Relay URLs run until the optional direct-address section
+    let rest = &payload[sep_pos + 1..];
+    let (relays, addrs) = match rest.iter().position(|&b| b == ADDRS_SEPARATOR) {
+        Some(pos) => (&rest[..pos], Some(&rest[pos + 1..])),
+        None => (rest, None),
+    };
 
-    Ok((endpoint_id, relay_url))
+    let relay_urls = split_list(relays, "relay URL")?;
+    let direct_addrs = match addrs {
+        Some(addrs) => split_list(addrs, "direct address")?,
+        None => Vec::new(),
+    };
+
+    Ok(SessionCodeParts {
+        endpoint_id,
+        relay_urls,
+        direct_addrs,
+        key,
+    })
 }
 
 /// Export code functions to Lua via nvim-oxi.
@@ -63,6 +291,41 @@ pub fn code_ffi() -> Dictionary {
                 },
             )),
         ),
+        (
+            "encode_relays",
+            Object::from(Function::<(String, Vec<String>), String>::from_fn(
+                |(endpoint_id, relay_urls)| -> Result<String, nvim_oxi::Error> {
+                    match encode_relays(&endpoint_id, &relay_urls) {
+                        Ok(code) => Ok(code),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "encode_with_key",
+            Object::from(Function::<(String, String, String), String>::from_fn(
+                |(endpoint_id, relay_url, key)| -> Result<String, nvim_oxi::Error> {
+                    match encode_with_key(&endpoint_id, &relay_url, &key) {
+                        Ok(code) => Ok(code),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "encode_with_addrs",
+            Object::from(
+                Function::<(String, Vec<String>, Vec<String>), String>::from_fn(
+                    |(endpoint_id, relay_urls, direct_addrs)| -> Result<String, nvim_oxi::Error> {
+                        match encode_with_addrs(&endpoint_id, &relay_urls, &direct_addrs) {
+                            Ok(code) => Ok(code),
+                            Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                        }
+                    },
+                ),
+            ),
+        ),
         (
             "decode",
             Object::from(Function::<String, (String, String)>::from_fn(
@@ -74,6 +337,41 @@ pub fn code_ffi() -> Dictionary {
                 },
             )),
         ),
+        (
+            "decode_with_key",
+            Object::from(Function::<String, (String, String, String)>::from_fn(
+                |code| -> Result<(String, String, String), nvim_oxi::Error> {
+                    match decode_with_key(&code) {
+                        Ok((endpoint_id, relay_url, key)) => Ok((endpoint_id, relay_url, key)),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "decode_addrs",
+            Object::from(
+                Function::<String, (String, Vec<String>, Vec<String>)>::from_fn(
+                    |code| -> Result<(String, Vec<String>, Vec<String>), nvim_oxi::Error> {
+                        match decode_addrs(&code) {
+                            Ok(parts) => Ok(parts),
+                            Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                        }
+                    },
+                ),
+            ),
+        ),
+        (
+            "decode_relays",
+            Object::from(Function::<String, (String, Vec<String>)>::from_fn(
+                |code| -> Result<(String, Vec<String>), nvim_oxi::Error> {
+                    match decode_relays(&code) {
+                        Ok((endpoint_id, relay_urls)) => Ok((endpoint_id, relay_urls)),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
     ])
 }
 
@@ -122,4 +420,193 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("missing separator"));
     }
+
+    #[test]
+    fn test_v1_version_byte() {
+        let code = Base64UrlUnpadded::encode_string(b"\x01abc123xyz\x01https://relay.example.com");
+
+        let (id, url) = decode(&code).expect("decode");
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(url, "https://relay.example.com");
+    }
+
+    #[test]
+    fn test_legacy_unversioned_code() {
+        let legacy = Base64UrlUnpadded::encode_string(b"abc123xyz\x01https://relay.example.com");
+
+        let (id, url) = decode(&legacy).expect("decode");
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(url, "https://relay.example.com");
+    }
+
+    #[test]
+    fn test_unsupported_version() {
+        let code = Base64UrlUnpadded::encode_string(b"\x07abc123xyz\x01https://relay.example.com");
+
+        let result = decode(&code);
+        assert!(result.is_err());
+        assert!(
+            result
+                .unwrap_err()
+                .contains("Unsupported session code version")
+        );
+    }
+
+    #[test]
+    fn test_multiple_relays_roundtrip() {
+        let relays = ["https://relay-a.example.com", "https://relay-b.example.com"];
+
+        let code = encode_relays("abc123xyz", &relays).expect("encode");
+        let (id, urls) = decode_relays(&code).expect("decode");
+
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(urls, relays);
+
+        // Single-relay decode still yields the first relay
+        let (_, first) = decode(&code).expect("decode");
+        assert_eq!(first, relays[0]);
+    }
+
+    #[test]
+    fn test_single_relay_code_decodes_as_list() {
+        let code = encode("abc123xyz", "https://relay.example.com").expect("encode");
+
+        let (_, urls) = decode_relays(&code).expect("decode");
+        assert_eq!(urls, vec!["https://relay.example.com".to_string()]);
+    }
+
+    #[test]
+    fn test_relay_with_separator_rejected() {
+        let result = encode_relays("abc", &["https://a\x02b"]);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_relay_with_key_or_addrs_separator_rejected() {
+        assert!(encode_relays("abc", &["https://a\x03b"]).is_err());
+        assert!(encode_relays("abc", &["https://a\x04b"]).is_err());
+    }
+
+    #[test]
+    fn test_endpoint_with_separator_rejected() {
+        for sep in ["\x01", "\x02", "\x03", "\x04"] {
+            let endpoint_id = format!("abc{sep}xyz");
+            let result = encode(&endpoint_id, "https://relay.example.com");
+            assert!(result.is_err(), "separator {sep:?} accepted");
+        }
+    }
+
+    #[test]
+    fn test_empty_relay_section_decodes_as_empty_list() {
+        let key = crate::crypto::generate_key();
+
+        let code = encode_with_key("abc123xyz", "", &key).expect("encode");
+        let (id, urls) = decode_relays(&code).expect("decode");
+        assert_eq!(id, "abc123xyz");
+        assert!(urls.is_empty());
+
+        let (_, url, decoded_key) = decode_with_key(&code).expect("decode");
+        assert!(url.is_empty());
+        assert_eq!(decoded_key, key);
+    }
+
+    #[test]
+    fn test_checksum_valid() {
+        let code = encode("abc123xyz", "https://relay.example.com").expect("encode");
+
+        let payload = Base64UrlUnpadded::decode_vec(&code).expect("base64");
+        assert_eq!(payload[0], VERSION_V2);
+        assert!(decode(&code).is_ok());
+    }
+
+    #[test]
+    fn test_checksum_detects_corruption() {
+        let code = encode("abc123xyz", "https://relay.example.com").expect("encode");
+
+        let mut payload = Base64UrlUnpadded::decode_vec(&code).expect("base64");
+        payload[5] ^= 0x01;
+        let corrupted = Base64UrlUnpadded::encode_string(&payload);
+
+        let result = decode(&corrupted);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_checksum_detects_truncation() {
+        let code = encode("abc123xyz", "https://relay.example.com").expect("encode");
+
+        let mut payload = Base64UrlUnpadded::decode_vec(&code).expect("base64");
+        payload.truncate(payload.len() - 3);
+        let truncated = Base64UrlUnpadded::encode_string(&payload);
+
+        let result = decode(&truncated);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("checksum mismatch"));
+    }
+
+    #[test]
+    fn test_key_bearing_code_roundtrip() {
+        let key = crate::crypto::generate_key();
+
+        let code = encode_with_key("abc123xyz", "https://relay.example.com", &key).expect("encode");
+        let (id, url, decoded_key) = decode_with_key(&code).expect("decode");
+
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(url, "https://relay.example.com");
+        assert_eq!(decoded_key, key);
+
+        // Plain decode still works and ignores the key
+        let (id, url) = decode(&code).expect("plain decode");
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(url, "https://relay.example.com");
+    }
+
+    #[test]
+    fn test_keyless_code_decodes_with_empty_key() {
+        let code = encode("abc123xyz", "https://relay.example.com").expect("encode");
+
+        let (id, url, key) = decode_with_key(&code).expect("decode");
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(url, "https://relay.example.com");
+        assert!(key.is_empty());
+    }
+
+    #[test]
+    fn test_encode_with_invalid_key() {
+        let result = encode_with_key("abc", "https://relay.example.com", "short");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Invalid key"));
+    }
+
+    #[test]
+    fn test_direct_addrs_roundtrip() {
+        let relays = ["https://relay.example.com"];
+        let addrs = ["192.168.1.10:4433", "[fe80::1]:4433"];
+
+        let code = encode_with_addrs("abc123xyz", &relays, &addrs).expect("encode");
+        let (id, urls, decoded_addrs) = decode_addrs(&code).expect("decode");
+
+        assert_eq!(id, "abc123xyz");
+        assert_eq!(urls, relays);
+        assert_eq!(decoded_addrs, addrs);
+
+        // Relay-only decoders are unaffected by the address section
+        let (_, url) = decode(&code).expect("decode");
+        assert_eq!(url, relays[0]);
+    }
+
+    #[test]
+    fn test_direct_addrs_absent() {
+        let code = encode("abc123xyz", "https://relay.example.com").expect("encode");
+
+        let (_, _, addrs) = decode_addrs(&code).expect("decode");
+        assert!(addrs.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_direct_addr_rejected() {
+        let result = encode_with_addrs("abc", &["https://relay.example.com"], &["not-an-addr"]);
+        assert!(result.is_err());
+    }
 }
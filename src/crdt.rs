@@ -1,31 +1,176 @@
 use base64::Engine;
 use log::{debug, error, info, warn};
 use loro::{
-    ContainerID, EventTriggerKind, ExportMode, LoroDoc, LoroText, Subscription, TextDelta,
-    VersionVector, event::Diff,
+    ChangeMeta, ContainerID, ContainerType, Counter, EventTriggerKind, ExpandType, ExportMode,
+    Frontiers, ID, LORO_VERSION, LoroDoc, LoroText, LoroValue, StyleConfig, Subscription,
+    TextDelta, UndoManager, VersionRange, VersionVector,
+    cursor::{Cursor, PosType, Side},
+    event::Diff,
 };
 use nvim_oxi::{Dictionary, Function, Object};
 use parking_lot::Mutex;
+use serde::Serialize;
 use std::{
-    collections::HashMap,
-    sync::{Arc, LazyLock},
+    collections::{HashMap, HashSet, VecDeque},
+    fs,
+    path::Path,
+    sync::{
+        Arc, LazyLock,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
 };
 use uuid::Uuid;
 
-/// Container ID for our root "content" text container
-const CONTENT_CONTAINER_ID: &str = "cid:root-content:Text";
+/// Name of the root text container used when Lua passes an empty name
+const DEFAULT_CONTAINER: &str = "content";
+
+/// Number of recent edit IDs remembered for duplicate detection
+const MAX_RECENT_EDIT_IDS: usize = 256;
+
+/// Default limit on base64 payloads accepted from Lua (64 MiB)
+const DEFAULT_MAX_UPDATE_B64_LEN: usize = 64 * 1024 * 1024;
+
+/// Current limit on base64 payloads accepted from Lua (0 disables the check)
+static MAX_UPDATE_B64_LEN: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_UPDATE_B64_LEN);
+
+/// Default limit on queued remote deltas per container
+const DEFAULT_MAX_PENDING_DELTAS: usize = 100_000;
+
+/// Current limit on queued remote deltas per container (0 disables the cap)
+static MAX_PENDING_DELTAS: AtomicUsize = AtomicUsize::new(DEFAULT_MAX_PENDING_DELTAS);
+
+/// Entry returned by the delta polls when queued deltas were dropped;
+/// the caller must resync the whole buffer from doc_get_text.
+const OVERFLOW_SENTINEL: &str = r#"{"type":"overflow"}"#;
+
+/// Current limit on base64 payloads accepted from Lua (0 if disabled)
+pub(crate) fn max_update_b64_len() -> usize {
+    MAX_UPDATE_B64_LEN.load(Ordering::Relaxed)
+}
+
+/// Reject a base64 payload over the configured limit before it is decoded.
+/// Shared by every FFI entry point that takes CRDT data from Lua.
+pub(crate) fn check_update_size(b64_len: usize) -> Result<(), String> {
+    let max = max_update_b64_len();
+    if max > 0 && b64_len > max {
+        return Err(format!(
+            "payload of {} bytes exceeds the {} byte limit",
+            b64_len, max
+        ));
+    }
+    Ok(())
+}
 
 /// Global registry of CRDT documents
 static DOCS: LazyLock<Mutex<HashMap<Uuid, CrdtDoc>>> = LazyLock::new(|| Mutex::new(HashMap::new()));
 
+/// A remote change in Neovim buffer coordinates (0-based rows, byte columns).
+/// Edits are sequential: each one applies to the buffer left by the previous.
+#[derive(Debug, PartialEq, Serialize)]
+struct LineEdit {
+    start_row: usize,
+    start_col: usize,
+    end_row: usize,
+    end_col: usize,
+    text: String,
+}
+
+/// Row and byte column of a byte offset in `text`
+fn row_col(text: &str, byte_pos: usize) -> (usize, usize) {
+    let prefix = &text[..byte_pos];
+    let row = prefix.matches('\n').count();
+    let col = prefix.rfind('\n').map_or(byte_pos, |i| byte_pos - i - 1);
+    (row, col)
+}
+
+/// Byte offset of a UTF-16 code unit offset in `text`.
+/// None if the offset is past the end or splits a surrogate pair.
+fn utf16_to_byte(text: &str, utf16_pos: usize) -> Option<usize> {
+    let mut units = 0;
+    for (byte, c) in text.char_indices() {
+        if units == utf16_pos {
+            return Some(byte);
+        }
+        units += c.len_utf16();
+        if units > utf16_pos {
+            return None;
+        }
+    }
+    (units == utf16_pos).then_some(text.len())
+}
+
+/// UTF-16 code unit offset of a byte offset in `text`.
+/// None if the offset is past the end or inside a multi-byte character.
+fn byte_to_utf16(text: &str, byte_pos: usize) -> Option<usize> {
+    let prefix = text.get(..byte_pos)?;
+    Some(prefix.chars().map(char::len_utf16).sum())
+}
+
+/// Convert deltas applied to `base` into line-oriented edits.
+/// Delta lengths are Loro event indices (Unicode scalar values), so positions are
+/// walked character by character to produce byte columns.
+fn deltas_to_line_edits(base: &str, deltas: &[TextDeltaEvent]) -> Vec<LineEdit> {
+    // Byte offset `chars` characters after `pos` in `text`
+    let advance = |text: &str, pos: usize, chars: usize| {
+        text[pos..]
+            .char_indices()
+            .nth(chars)
+            .map_or(text.len(), |(i, _)| pos + i)
+    };
+
+    let mut current = base.to_string();
+    let mut pos = 0;
+    let mut edits = Vec::new();
+    for delta in deltas {
+        match delta {
+            TextDeltaEvent::Retain { len, .. } => pos = advance(&current, pos, *len),
+            TextDeltaEvent::Insert { text, .. } => {
+                let (row, col) = row_col(&current, pos);
+                edits.push(LineEdit {
+                    start_row: row,
+                    start_col: col,
+                    end_row: row,
+                    end_col: col,
+                    text: text.clone(),
+                });
+                current.insert_str(pos, text);
+                pos += text.len();
+            }
+            TextDeltaEvent::Delete { len } => {
+                let end = advance(&current, pos, *len);
+                let (start_row, start_col) = row_col(&current, pos);
+                let (end_row, end_col) = row_col(&current, end);
+                edits.push(LineEdit {
+                    start_row,
+                    start_col,
+                    end_row,
+                    end_col,
+                    text: String::new(),
+                });
+                current.replace_range(pos..end, "");
+            }
+        }
+    }
+    edits
+}
+
+/// Formatting attributes (marks) attached to a delta, e.g. {"bold": true}
+type Attributes = HashMap<String, LoroValue>;
+
 /// A TextDelta event for FFI serialization
 /// Represents a single operation in the Quill delta format
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum TextDeltaEvent {
-    /// Skip forward by `len` bytes (no change)
-    Retain { len: usize },
-    /// Insert `text` at current position
-    Insert { text: String },
+    /// Skip forward by `len` bytes, applying `attributes` if present
+    Retain {
+        len: usize,
+        attributes: Option<Attributes>,
+    },
+    /// Insert `text` at current position, formatted with `attributes` if present
+    Insert {
+        text: String,
+        attributes: Option<Attributes>,
+    },
     /// Delete `len` bytes at current position
     Delete { len: usize },
 }
@@ -34,13 +179,18 @@ impl TextDeltaEvent {
     /// Serialize to JSON string for FFI
     fn to_json(&self) -> String {
         match self {
-            TextDeltaEvent::Retain { len } => {
-                format!("{{\"type\":\"retain\",\"len\":{}}}", len)
+            TextDeltaEvent::Retain { len, attributes } => {
+                format!(
+                    "{{\"type\":\"retain\",\"len\":{}{}}}",
+                    len,
+                    attributes_json(attributes)
+                )
             }
-            TextDeltaEvent::Insert { text } => {
+            TextDeltaEvent::Insert { text, attributes } => {
                 format!(
-                    "{{\"type\":\"insert\",\"text\":{}}}",
-                    serde_json::to_string(text).unwrap_or_else(|_| "\"\"".to_string())
+                    "{{\"type\":\"insert\",\"text\":{}{}}}",
+                    serde_json::to_string(text).unwrap_or_else(|_| "\"\"".to_string()),
+                    attributes_json(attributes)
                 )
             }
             TextDeltaEvent::Delete { len } => {
@@ -50,28 +200,158 @@ impl TextDeltaEvent {
     }
 }
 
+/// Serialize attributes as a trailing `,"attributes":{...}` field, or nothing if absent
+fn attributes_json(attributes: &Option<Attributes>) -> String {
+    match attributes {
+        Some(attrs) if !attrs.is_empty() => match serde_json::to_string(attrs) {
+            Ok(json) => format!(",\"attributes\":{}", json),
+            Err(_) => String::new(),
+        },
+        _ => String::new(),
+    }
+}
+
 impl From<&TextDelta> for TextDeltaEvent {
     fn from(delta: &TextDelta) -> Self {
         match delta {
-            TextDelta::Retain { retain, .. } => TextDeltaEvent::Retain { len: *retain },
-            TextDelta::Insert { insert, .. } => TextDeltaEvent::Insert {
+            TextDelta::Retain { retain, attributes } => TextDeltaEvent::Retain {
+                len: *retain,
+                attributes: attributes
+                    .as_ref()
+                    .map(|attrs| attrs.iter().map(|(k, v)| (k.clone(), v.clone())).collect()),
+            },
+            TextDelta::Insert { insert, attributes } => TextDeltaEvent::Insert {
                 text: insert.clone(),
+                attributes: attributes
+                    .as_ref()
+                    .map(|attrs| attrs.iter().map(|(k, v)| (k.clone(), v.clone())).collect()),
             },
             TextDelta::Delete { delete } => TextDeltaEvent::Delete { len: *delete },
         }
     }
 }
 
-/// Thread-safe queue for pending TextDelta events from subscriptions
-type DeltaQueue = Arc<Mutex<Vec<TextDeltaEvent>>>;
+/// A single step in an exported session transcript
+#[derive(Debug, Serialize)]
+struct TranscriptEntry {
+    /// Peer ID of the author (string to survive the Lua number conversion)
+    author: String,
+    /// Counter of the first op in the change
+    counter: Counter,
+    /// Lamport timestamp of the change
+    lamport: u32,
+    /// Unix timestamp in seconds (0 if the author did not record timestamps)
+    timestamp: i64,
+    /// Commit message, if any
+    message: Option<String>,
+    /// Number of ops in the change
+    ops: usize,
+    /// Text content as of this change
+    text: String,
+}
+
+/// Size statistics for a document
+#[derive(Debug, Serialize)]
+struct DocStats {
+    /// Size of a full snapshot export
+    snapshot_bytes: usize,
+    /// Size of the complete update log export
+    updates_bytes: usize,
+    /// Length of the "content" text in UTF-8 bytes
+    text_bytes: usize,
+    /// Length of the "content" text in Unicode scalar values
+    text_chars: usize,
+}
+
+/// Result of a dry-run import
+#[derive(Debug, Serialize)]
+struct UpdateValidation {
+    /// Whether the update would import cleanly
+    valid: bool,
+    /// Length of the "content" text in UTF-8 bytes after the import
+    new_size: usize,
+    /// Why the update was rejected
+    error: Option<String>,
+}
+
+/// A root container of the document
+#[derive(Debug, PartialEq, Serialize)]
+struct ContainerInfo {
+    name: String,
+    /// Loro container type, e.g. "Text" or "Map"
+    #[serde(rename = "type")]
+    kind: String,
+}
+
+/// A single change in the document history
+#[derive(Debug, Serialize)]
+struct HistoryEntry {
+    /// Peer ID of the author (string to survive the Lua number conversion)
+    peer: String,
+    /// First op counter in the change
+    counter_start: Counter,
+    /// One past the last op counter in the change
+    counter_end: Counter,
+    /// Lamport timestamp of the change
+    lamport: u32,
+    /// Unix timestamp in seconds (0 if the author did not record timestamps)
+    timestamp: i64,
+    /// Commit message, if any
+    message: Option<String>,
+}
+
+/// Result of importing a remote update
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ImportOutcome {
+    /// All ops in the update were applied
+    Applied,
+    /// Some ops are waiting on missing dependencies and were not applied yet
+    Pending,
+    /// The update exceeded the size limit and was rejected without decoding
+    TooLarge,
+    /// The update could not be decoded or imported
+    Failed,
+}
+
+impl ImportOutcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImportOutcome::Applied => "applied",
+            ImportOutcome::Pending => "pending",
+            ImportOutcome::TooLarge => "too_large",
+            ImportOutcome::Failed => "failed",
+        }
+    }
+}
+
+/// Thread-safe queues for pending TextDelta events from subscriptions, keyed by container name
+type DeltaQueue = Arc<Mutex<HashMap<String, Vec<TextDeltaEvent>>>>;
+
+/// Containers whose delta queue hit the cap and dropped its oldest deltas
+type OverflowSet = Arc<Mutex<HashSet<String>>>;
+
+/// Resolve a container name from Lua, falling back to the default "content" container
+fn container_name(name: &str) -> &str {
+    if name.is_empty() {
+        DEFAULT_CONTAINER
+    } else {
+        name
+    }
+}
 
 /// A CRDT document instance wrapping LoroDoc with LoroText
 struct CrdtDoc {
     id: Uuid,
     doc: LoroDoc,
-    /// Pending TextDelta events from remote updates (for Lua to poll)
+    /// Pending TextDelta events from remote updates per container (for Lua to poll)
     /// Uses Arc<Mutex<>> for thread-safe access from subscription callback
     pending_deltas: DeltaQueue,
+    /// Pending TextDelta events from time-travel checkouts, kept apart from remote deltas
+    checkout_deltas: DeltaQueue,
+    /// Containers whose pending_deltas overflowed since they were last polled
+    overflowed: OverflowSet,
+    /// Whether local commits are queued as deltas too (shared with the subscription)
+    echo_local: Arc<AtomicBool>,
     /// Subscription handle - must be kept alive for callbacks to fire
     #[allow(dead_code)]
     subscription: Option<Subscription>,
@@ -79,6 +359,19 @@ struct CrdtDoc {
     applying_local: bool,
     /// Last known text content (for debugging)
     last_text: String,
+    /// Recently applied edit IDs (bounded, oldest first) for duplicate detection
+    recent_edit_ids: VecDeque<String>,
+    /// Op ranges of imported updates still waiting on missing dependencies
+    pending_imports: Vec<VersionRange>,
+    /// Undo stack for local edits (remote imports are never recorded)
+    undo: UndoManager,
+    /// "content" text before the oldest unpolled delta, for line-mode polling
+    line_base: Option<String>,
+    /// Whether a batch is open; apply_edit defers its commit until commit_batch
+    batch_open: bool,
+    /// Detached editing branch. While set, local edits go to this fork instead of
+    /// the main line, so they are neither exported to peers nor mixed with remote deltas.
+    branch: Option<LoroDoc>,
 }
 
 impl CrdtDoc {
@@ -86,44 +379,86 @@ impl CrdtDoc {
         // Create empty LoroDoc - do NOT initialize containers
         // Containers are created lazily when first accessed for write,
         // or when importing from another peer's state
-        let doc = LoroDoc::new();
-        let pending_deltas: DeltaQueue = Arc::new(Mutex::new(Vec::new()));
+        let doc = Self::new_loro_doc();
+        let pending_deltas: DeltaQueue = Arc::new(Mutex::new(HashMap::new()));
+        let checkout_deltas: DeltaQueue = Arc::new(Mutex::new(HashMap::new()));
+        let overflowed: OverflowSet = Arc::new(Mutex::new(HashSet::new()));
+        let echo_local = Arc::new(AtomicBool::new(false));
 
-        // Set up subscription to capture TextDelta events from imports
-        let subscription = Self::setup_subscription(&doc, id, Arc::clone(&pending_deltas));
+        // Set up subscription to capture TextDelta events from imports and checkouts
+        let subscription = Self::setup_subscription(
+            &doc,
+            id,
+            Arc::clone(&pending_deltas),
+            Arc::clone(&checkout_deltas),
+            Arc::clone(&overflowed),
+            Arc::clone(&echo_local),
+        );
+        let undo = UndoManager::new(&doc);
 
         Self {
             id,
             doc,
             pending_deltas,
+            checkout_deltas,
+            overflowed,
+            echo_local,
             subscription: Some(subscription),
             applying_local: false,
             last_text: String::new(),
+            recent_edit_ids: VecDeque::new(),
+            pending_imports: Vec::new(),
+            undo,
+            line_base: None,
+            batch_open: false,
+            branch: None,
         }
     }
 
+    /// Create an empty LoroDoc with the settings every CrdtDoc relies on
+    fn new_loro_doc() -> LoroDoc {
+        let doc = LoroDoc::new();
+        // Record commit timestamps so history and transcripts carry wall-clock times
+        doc.set_record_timestamp(true);
+        // Allow arbitrary mark keys (bold, italic, link, ...) to be used without registration
+        doc.config_default_text_style(Some(StyleConfig {
+            expand: ExpandType::After,
+        }));
+        doc
+    }
+
     /// Set up subscription to the root containers to capture TextDelta events
-    fn setup_subscription(doc: &LoroDoc, id: Uuid, pending: DeltaQueue) -> Subscription {
-        // Subscribe to all root containers - we'll filter for "content" text container
+    fn setup_subscription(
+        doc: &LoroDoc,
+        id: Uuid,
+        pending: DeltaQueue,
+        checkout: DeltaQueue,
+        overflowed: OverflowSet,
+        echo_local: Arc<AtomicBool>,
+    ) -> Subscription {
+        // Subscribe to all root containers - deltas are routed per root text container
         doc.subscribe_root(Arc::new(move |event| {
-            // Only process events from Import (remote updates)
-            // Skip Local commits (our own edits) and Checkout (time travel)
-            if !matches!(event.triggered_by, EventTriggerKind::Import) {
-                return;
-            }
+            // Import (remote updates) and Checkout (time travel) go to separate queues;
+            // Local commits (our own edits) are skipped unless echo is enabled
+            let (queue, source) = match event.triggered_by {
+                EventTriggerKind::Import => (&pending, "import"),
+                EventTriggerKind::Checkout => (&checkout, "checkout"),
+                EventTriggerKind::Local if echo_local.load(Ordering::Relaxed) => {
+                    (&pending, "local")
+                }
+                EventTriggerKind::Local => return,
+            };
 
             for container_diff in &event.events {
-                // Check if this is our "content" text container
-                // The container ID for root text is "cid:root-content:Text"
-                let is_content = match &container_diff.target {
-                    ContainerID::Root { name, .. } => name.as_str() == "content",
-                    ContainerID::Normal { .. } => false,
+                // Only root text containers (e.g. "cid:root-content:Text") map to buffers
+                let name = match &container_diff.target {
+                    ContainerID::Root {
+                        name,
+                        container_type: ContainerType::Text,
+                    } => name.to_string(),
+                    _ => continue,
                 };
 
-                if !is_content {
-                    continue;
-                }
-
                 // Extract TextDelta events
                 if let Diff::Text(deltas) = &container_diff.diff {
                     let delta_events: Vec<TextDeltaEvent> =
@@ -131,46 +466,218 @@ impl CrdtDoc {
 
                     if !delta_events.is_empty() {
                         debug!(
-                            "[crdt:{}] Subscription received {} delta events from import",
+                            "[crdt:{}] Subscription received {} delta events for '{}' from {}",
                             id,
-                            delta_events.len()
+                            delta_events.len(),
+                            name,
+                            source
                         );
-                        pending.lock().extend(delta_events);
+                        let mut queue = queue.lock();
+                        let queued = queue.entry(name.clone()).or_default();
+                        queued.extend(delta_events);
+
+                        // Drop the oldest deltas rather than grow without bound
+                        let max = MAX_PENDING_DELTAS.load(Ordering::Relaxed);
+                        if max > 0 && queued.len() > max {
+                            let excess = queued.len() - max;
+                            queued.drain(..excess);
+                            warn!(
+                                "[crdt:{}] Delta queue for '{}' overflowed, dropped {} deltas",
+                                id, name, excess
+                            );
+                            overflowed.lock().insert(name);
+                        }
                     }
                 }
             }
         }))
     }
 
-    /// Check if the "content" container exists in the document
-    fn has_content(&self) -> bool {
-        let container_id: ContainerID = CONTENT_CONTAINER_ID
-            .try_into()
-            .expect("invalid container ID constant");
-        self.doc.has_container(&container_id)
+    /// The document local reads and edits go to: the detached branch if one is open
+    fn active_doc(&self) -> &LoroDoc {
+        self.branch.as_ref().unwrap_or(&self.doc)
+    }
+
+    /// Start a detached editing branch at the current version.
+    /// Returns false if a branch is already open.
+    fn start_branch(&mut self) -> bool {
+        if self.branch.is_some() {
+            return false;
+        }
+        let branch = self.doc.fork();
+        branch.set_record_timestamp(true);
+        self.branch = Some(branch);
+        true
+    }
+
+    /// Merge the detached branch into the main line as ordinary local changes.
+    /// Returns false if no branch is open or the merge failed.
+    fn merge_branch(&mut self) -> bool {
+        let Some(branch) = self.branch.take() else {
+            return false;
+        };
+
+        let merged = branch
+            .export(ExportMode::updates(&self.doc.oplog_vv()))
+            .map_err(|e| e.to_string())
+            .and_then(|bytes| self.doc.import(&bytes).map_err(|e| e.to_string()));
+        // Remote deltas queued while the branch was open don't match the buffer
+        self.clear_pending_deltas();
+        self.last_text = self.get_text(DEFAULT_CONTAINER);
+
+        match merged {
+            Ok(_) => true,
+            Err(e) => {
+                error!("[crdt:{}] Failed to merge branch: {}", self.id, e);
+                false
+            }
+        }
+    }
+
+    /// Drop the detached branch and its edits. Returns false if no branch is open.
+    fn discard_branch(&mut self) -> bool {
+        if self.branch.take().is_none() {
+            return false;
+        }
+        self.clear_pending_deltas();
+        self.last_text = self.get_text(DEFAULT_CONTAINER);
+        true
+    }
+
+    /// Check out the document state at the given base64-encoded frontiers.
+    /// The doc is detached afterwards: remote updates are recorded but not shown
+    /// and local edits fail until checkout_to_latest. Returns true on success.
+    fn checkout_b64(&mut self, frontiers_b64: &str) -> bool {
+        let Some(frontiers) = self.decode_frontiers_b64(frontiers_b64) else {
+            return false;
+        };
+        match self.doc.checkout(&frontiers) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("[crdt:{}] Failed to check out version: {}", self.id, e);
+                false
+            }
+        }
+    }
+
+    /// Return from a checked-out version to the latest state.
+    /// Returns false if the doc was not detached.
+    fn checkout_to_latest(&mut self) -> bool {
+        if !self.doc.is_detached() {
+            return false;
+        }
+        self.doc.checkout_to_latest();
+        true
+    }
+
+    /// Check if the named root text container exists in the document
+    fn has_container(&self, name: &str) -> bool {
+        // LoroDoc::has_container reports every root ID as present, so look at
+        // the roots that actually have state
+        let LoroValue::Map(roots) = self.active_doc().get_value() else {
+            return false;
+        };
+        matches!(
+            roots.get(name),
+            Some(LoroValue::Container(id)) if id.container_type() == ContainerType::Text
+        )
+    }
+
+    /// Root containers of the document, sorted by name
+    fn list_containers(&self) -> Vec<ContainerInfo> {
+        let LoroValue::Map(roots) = self.active_doc().get_value() else {
+            return Vec::new();
+        };
+        let mut containers: Vec<ContainerInfo> = roots
+            .iter()
+            .filter_map(|(name, value)| match value {
+                LoroValue::Container(ContainerID::Root { container_type, .. }) => {
+                    Some(ContainerInfo {
+                        name: name.clone(),
+                        kind: container_type.to_string(),
+                    })
+                }
+                _ => None,
+            })
+            .collect();
+        containers.sort_by(|a, b| a.name.cmp(&b.name));
+        containers
     }
 
-    /// Get the "content" text container, creating it if it doesn't exist.
+    /// Get the named text container, creating it if it doesn't exist.
     /// WARNING: This creates the container with this peer's ID if it doesn't exist.
     /// Only call this when you intend to write to the container.
-    fn text_for_write(&self) -> LoroText {
-        self.doc.get_text("content")
+    fn text_for_write(&self, name: &str) -> LoroText {
+        self.active_doc().get_text(name)
     }
 
-    /// Get the text content. Returns empty string if container doesn't exist yet.
-    fn get_text(&self) -> String {
-        if self.has_content() {
-            self.doc.get_text("content").to_string()
+    /// Get the text content of a container. Returns empty string if it doesn't exist yet.
+    fn get_text(&self, name: &str) -> String {
+        if self.has_container(name) {
+            self.active_doc().get_text(name).to_string()
         } else {
             String::new()
         }
     }
 
-    fn set_text(&mut self, content: &str) {
+    /// Get the text between two byte offsets without materializing the whole string.
+    /// The range is clamped to the current text length.
+    fn get_text_range(&self, start_byte: usize, end_byte: usize) -> String {
+        if !self.has_container(DEFAULT_CONTAINER) {
+            return String::new();
+        }
+
+        let text = self.active_doc().get_text(DEFAULT_CONTAINER);
+        let len = text.len_utf8();
+        let start = start_byte.min(len);
+        let end = end_byte.min(len).max(start);
+        if start == end {
+            return String::new();
+        }
+
+        match text.slice_delta(start, end, PosType::Bytes) {
+            Ok(deltas) => deltas
+                .into_iter()
+                .filter_map(|delta| match delta {
+                    TextDelta::Insert { insert, .. } => Some(insert),
+                    _ => None,
+                })
+                .collect(),
+            Err(e) => {
+                warn!(
+                    "[crdt:{}] Failed to read range [{}, {}): {}",
+                    self.id, start, end, e
+                );
+                String::new()
+            }
+        }
+    }
+
+    /// Before a local edit to `name`: if it will be echoed into an empty queue,
+    /// record the current text as the base for line-mode polling, as imports do
+    fn prepare_local_echo(&mut self, name: &str) {
+        if !self.echo_local.load(Ordering::Relaxed)
+            || name != DEFAULT_CONTAINER
+            || self.branch.is_some()
+        {
+            return;
+        }
+        let queue_empty = self
+            .pending_deltas
+            .lock()
+            .get(DEFAULT_CONTAINER)
+            .is_none_or(|deltas| deltas.is_empty());
+        if queue_empty {
+            self.line_base = Some(self.get_text(DEFAULT_CONTAINER));
+        }
+    }
+
+    fn set_text(&mut self, name: &str, content: &str) {
         self.applying_local = true;
+        self.prepare_local_echo(name);
 
         // Use text_for_write since we're modifying
-        let text = self.text_for_write();
+        let text = self.text_for_write(name);
         let current_len = text.len_utf8();
 
         // Delete all existing content
@@ -191,17 +698,21 @@ impl CrdtDoc {
             return;
         }
 
-        // Commit to trigger subscription (but we filter out local events)
-        self.doc.commit();
+        // Commit to trigger subscription (local events are only queued in echo mode),
+        // unless a batch is collecting edits
+        if !self.batch_open {
+            self.active_doc().commit();
+        }
         self.last_text = content.to_string();
         self.applying_local = false;
     }
 
-    fn apply_edit(&mut self, start_byte: usize, end_byte: usize, new_text: &str) {
+    fn apply_edit(&mut self, name: &str, start_byte: usize, end_byte: usize, new_text: &str) {
         self.applying_local = true;
+        self.prepare_local_echo(name);
 
         // Use text_for_write since we're modifying
-        let text = self.text_for_write();
+        let text = self.text_for_write(name);
         let current_len = text.len_utf8();
 
         // Clamp start and end to valid range
@@ -227,12 +738,92 @@ impl CrdtDoc {
             return;
         }
 
-        // Commit to finalize the transaction
-        self.doc.commit();
-        self.last_text = self.get_text();
+        // Commit to finalize the transaction, unless a batch is collecting edits
+        if !self.batch_open {
+            self.active_doc().commit();
+        }
+        self.last_text = self.get_text(name);
         self.applying_local = false;
     }
 
+    /// Start collecting apply_edit calls into a single commit.
+    /// Returns false if a batch is already open.
+    fn begin_batch(&mut self) -> bool {
+        if self.batch_open {
+            return false;
+        }
+        self.batch_open = true;
+        true
+    }
+
+    /// Commit all edits made since begin_batch as one change.
+    /// Returns false if no batch was open.
+    fn commit_batch(&mut self) -> bool {
+        if !self.batch_open {
+            return false;
+        }
+        self.batch_open = false;
+        self.active_doc().commit();
+        true
+    }
+
+    /// Apply a formatting mark to the byte range [start_byte, end_byte) of the "content" text.
+    fn mark(&mut self, start_byte: usize, end_byte: usize, key: &str, value: LoroValue) -> bool {
+        let text = self.text_for_write(DEFAULT_CONTAINER);
+        if let Err(e) = text.mark_utf8(start_byte..end_byte, key, value) {
+            error!("[crdt:{}] Failed to mark '{}': {}", self.id, key, e);
+            return false;
+        }
+        if !self.batch_open {
+            self.active_doc().commit();
+        }
+        true
+    }
+
+    /// Remove a formatting mark from the byte range [start_byte, end_byte) of the "content" text.
+    fn unmark(&mut self, start_byte: usize, end_byte: usize, key: &str) -> bool {
+        let text = self.text_for_write(DEFAULT_CONTAINER);
+        // LoroText has no byte-based unmark, so convert to Unicode offsets first
+        let to_unicode = |pos| text.convert_pos(pos, PosType::Bytes, PosType::Unicode);
+        let (Some(start), Some(end)) = (to_unicode(start_byte), to_unicode(end_byte)) else {
+            error!(
+                "[crdt:{}] Invalid unmark range [{}, {})",
+                self.id, start_byte, end_byte
+            );
+            return false;
+        };
+        if let Err(e) = text.unmark(start..end, key) {
+            error!("[crdt:{}] Failed to unmark '{}': {}", self.id, key, e);
+            return false;
+        }
+        if !self.batch_open {
+            self.active_doc().commit();
+        }
+        true
+    }
+
+    /// Apply a local edit unless `edit_id` was already seen recently.
+    /// Returns false if the edit was a duplicate and ignored.
+    fn apply_edit_once(
+        &mut self,
+        edit_id: &str,
+        start_byte: usize,
+        end_byte: usize,
+        new_text: &str,
+    ) -> bool {
+        if self.recent_edit_ids.iter().any(|seen| seen == edit_id) {
+            return false;
+        }
+
+        if self.recent_edit_ids.len() >= MAX_RECENT_EDIT_IDS {
+            self.recent_edit_ids.pop_front();
+        }
+        self.recent_edit_ids.push_back(edit_id.to_string());
+
+        self.apply_edit(DEFAULT_CONTAINER, start_byte, end_byte, new_text);
+        true
+    }
+
     fn version_vector(&self) -> VersionVector {
         self.doc.oplog_vv()
     }
@@ -244,6 +835,17 @@ impl CrdtDoc {
     }
 
     fn apply_update_b64(&mut self, update_b64: &str) -> bool {
+        self.import_update_b64(update_b64) != ImportOutcome::Failed
+    }
+
+    /// Import a remote update and report whether it was fully applied
+    /// or left pending on missing dependencies.
+    fn import_update_b64(&mut self, update_b64: &str) -> ImportOutcome {
+        if let Err(e) = check_update_size(update_b64.len()) {
+            error!("[crdt:{}] Rejecting update: {}", self.id, e);
+            return ImportOutcome::TooLarge;
+        }
+
         let update_bytes = match base64::engine::general_purpose::STANDARD.decode(update_b64) {
             Ok(bytes) => bytes,
             Err(e) => {
@@ -253,11 +855,17 @@ impl CrdtDoc {
                     e,
                     update_b64.len()
                 );
-                return false;
+                return ImportOutcome::Failed;
             }
         };
 
-        let text_before = self.get_text();
+        self.import_update_bytes(&update_bytes)
+    }
+
+    /// Import raw update bytes, queueing deltas as for any remote update.
+    /// Unlike import_update_b64 there is no size check, as the bytes don't come from Lua.
+    fn import_update_bytes(&mut self, update_bytes: &[u8]) -> ImportOutcome {
+        let text_before = self.get_text(DEFAULT_CONTAINER);
         info!(
             "[crdt:{}] Importing update: {} bytes raw, CRDT text before: {} bytes",
             self.id,
@@ -265,75 +873,670 @@ impl CrdtDoc {
             text_before.len()
         );
 
+        // Deltas from this import start from text_before unless older ones are still queued
+        let queue_empty = self
+            .pending_deltas
+            .lock()
+            .get(DEFAULT_CONTAINER)
+            .is_none_or(|deltas| deltas.is_empty());
+        if queue_empty && self.branch.is_none() {
+            self.line_base = Some(text_before.clone());
+        }
+
         // Import the update - this triggers the subscription callback
         // which will queue any TextDelta events to pending_deltas
-        if let Err(e) = self.doc.import(&update_bytes) {
-            error!("[crdt:{}] Failed to import update: {}", self.id, e);
-            return false;
-        }
+        let status = match self.doc.import(update_bytes) {
+            Ok(status) => status,
+            Err(e) => {
+                error!("[crdt:{}] Failed to import update: {}", self.id, e);
+                return ImportOutcome::Failed;
+            }
+        };
+
+        // This import may have unblocked earlier pending updates
+        let vv = self.version_vector();
+        self.pending_imports.retain(|range| {
+            range
+                .iter()
+                .any(|(peer, (_, end))| vv.get(peer).copied().unwrap_or(0) < *end)
+        });
+
+        let outcome = match status.pending {
+            Some(pending) => {
+                warn!(
+                    "[crdt:{}] Update left pending on missing dependencies",
+                    self.id
+                );
+                self.pending_imports.push(pending);
+                ImportOutcome::Pending
+            }
+            None => ImportOutcome::Applied,
+        };
 
         // Update last_text for debugging
-        self.last_text = self.get_text();
+        self.last_text = self.get_text(DEFAULT_CONTAINER);
         info!(
-            "[crdt:{}] Import successful, text now {} bytes (was {} bytes)",
+            "[crdt:{}] Import {}, text now {} bytes (was {} bytes)",
             self.id,
+            outcome.as_str(),
             self.last_text.len(),
             text_before.len()
         );
 
-        true
+        outcome
     }
 
-    fn encode_update_b64(&self, remote_vv_b64: &str) -> String {
-        let remote_vv_bytes = match base64::engine::general_purpose::STANDARD.decode(remote_vv_b64)
-        {
+    /// Import a remote update and return the "content" deltas it produced.
+    /// Deltas already queued are set aside for the duration of the import so the
+    /// result describes this update only; other containers' deltas stay queued.
+    fn import_update_capturing(
+        &mut self,
+        update_b64: &str,
+    ) -> (ImportOutcome, Vec<TextDeltaEvent>) {
+        let queued = std::mem::take(&mut *self.pending_deltas.lock());
+        // The line base belongs to the queued deltas, not the captured ones
+        let line_base = self.line_base.clone();
+        let outcome = self.import_update_b64(update_b64);
+        self.line_base = line_base;
+
+        let mut pending = self.pending_deltas.lock();
+        let mut captured = std::mem::replace(&mut *pending, queued);
+        let deltas = captured.remove(DEFAULT_CONTAINER).unwrap_or_default();
+        for (name, events) in captured {
+            pending.entry(name).or_default().extend(events);
+        }
+        (outcome, deltas)
+    }
+
+    /// Import an update into a throwaway fork of the main line to check that it
+    /// would be accepted, leaving this document untouched.
+    fn validate_update_b64(&self, update_b64: &str) -> UpdateValidation {
+        let rejected = |error: String| UpdateValidation {
+            valid: false,
+            new_size: 0,
+            error: Some(error),
+        };
+
+        if let Err(e) = check_update_size(update_b64.len()) {
+            return rejected(e);
+        }
+        let update_bytes = match base64::engine::general_purpose::STANDARD.decode(update_b64) {
+            Ok(bytes) => bytes,
+            Err(e) => return rejected(format!("invalid base64: {}", e)),
+        };
+
+        let scratch = self.doc.fork();
+        if let Err(e) = scratch.import(&update_bytes) {
+            return rejected(format!("import failed: {}", e));
+        }
+        UpdateValidation {
+            valid: true,
+            new_size: scratch.get_text(DEFAULT_CONTAINER).len_utf8(),
+            error: None,
+        }
+    }
+
+    /// Number of imported updates still waiting on missing dependencies
+    fn pending_count(&self) -> usize {
+        self.pending_imports.len()
+    }
+
+    /// Version the pending updates depend on: for each peer with a gap before
+    /// a pending range, the counter this doc must reach. None if nothing is missing.
+    ///
+    /// Only same-peer gaps are visible here; dependencies on other peers'
+    /// ops surface once these are filled and the import is retried.
+    fn missing_deps(&self) -> Option<VersionVector> {
+        let vv = self.version_vector();
+        let mut missing = VersionVector::new();
+        for range in &self.pending_imports {
+            for (&peer, &(start, _)) in range.iter() {
+                if vv.get(&peer).copied().unwrap_or(0) < start {
+                    missing.extend_to_include_end_id(ID::new(peer, start));
+                }
+            }
+        }
+
+        (!missing.is_empty()).then_some(missing)
+    }
+
+    /// Decode a base64-encoded version vector, logging any failure.
+    fn decode_vv_b64(&self, vv_b64: &str) -> Option<VersionVector> {
+        let vv_bytes = match base64::engine::general_purpose::STANDARD.decode(vv_b64) {
             Ok(bytes) => bytes,
             Err(e) => {
                 error!(
                     "[crdt:{}] Failed to decode version vector base64: {}",
                     self.id, e
                 );
-                return String::new();
+                return None;
             }
         };
 
-        let remote_vv = match VersionVector::decode(&remote_vv_bytes) {
-            Ok(vv) => vv,
+        match VersionVector::decode(&vv_bytes) {
+            Ok(vv) => Some(vv),
             Err(e) => {
                 error!("[crdt:{}] Failed to decode version vector: {}", self.id, e);
-                return String::new();
+                None
             }
+        }
+    }
+
+    fn encode_update_b64(&self, remote_vv_b64: &str) -> String {
+        let Some(remote_vv) = self.decode_vv_b64(remote_vv_b64) else {
+            return String::new();
         };
 
-        match self.doc.export(ExportMode::updates(&remote_vv)) {
-            Ok(bytes) => base64::engine::general_purpose::STANDARD.encode(&bytes),
+        self.export_updates_b64(&remote_vv)
+    }
+
+    /// Encode the oplog frontiers as base64.
+    fn frontiers_b64(&self) -> String {
+        let bytes = self.doc.oplog_frontiers().encode();
+        base64::engine::general_purpose::STANDARD.encode(&bytes)
+    }
+
+    /// Decode base64-encoded frontiers, logging any failure.
+    fn decode_frontiers_b64(&self, frontiers_b64: &str) -> Option<Frontiers> {
+        let bytes = match base64::engine::general_purpose::STANDARD.decode(frontiers_b64) {
+            Ok(bytes) => bytes,
             Err(e) => {
-                error!("[crdt:{}] Failed to export updates: {}", self.id, e);
-                String::new()
+                error!(
+                    "[crdt:{}] Failed to decode frontiers base64: {}",
+                    self.id, e
+                );
+                return None;
+            }
+        };
+        match Frontiers::decode(&bytes) {
+            Ok(frontiers) => Some(frontiers),
+            Err(e) => {
+                error!("[crdt:{}] Failed to decode frontiers: {}", self.id, e);
+                None
             }
         }
     }
 
-    fn encode_full_state_b64(&self) -> String {
-        match self.doc.export(ExportMode::all_updates()) {
-            Ok(bytes) => base64::engine::general_purpose::STANDARD.encode(&bytes),
+    /// Export the updates made after the given base64-encoded frontiers.
+    fn export_from_frontiers_b64(&self, frontiers_b64: &str) -> String {
+        let Some(frontiers) = self.decode_frontiers_b64(frontiers_b64) else {
+            return String::new();
+        };
+        let Some(vv) = self.doc.frontiers_to_vv(&frontiers) else {
+            warn!("[crdt:{}] Frontiers not found in local history", self.id);
+            return String::new();
+        };
+        self.export_updates_b64(&vv)
+    }
+
+    /// Export the updates missing from `vv` (empty on failure).
+    fn export_updates(&self, vv: &VersionVector) -> Vec<u8> {
+        match self.doc.export(ExportMode::updates(vv)) {
+            Ok(bytes) => bytes,
             Err(e) => {
-                error!("[crdt:{}] Failed to export full state: {}", self.id, e);
-                String::new()
+                error!("[crdt:{}] Failed to export updates: {}", self.id, e);
+                Vec::new()
             }
         }
     }
 
-    /// Poll for pending TextDelta events from remote updates
-    fn poll_deltas(&mut self) -> Vec<TextDeltaEvent> {
-        self.pending_deltas.lock().drain(..).collect()
+    /// Export the updates missing from `vv` as base64 (empty on failure).
+    fn export_updates_b64(&self, vv: &VersionVector) -> String {
+        let bytes = self.export_updates(vv);
+        if bytes.is_empty() {
+            return String::new();
+        }
+        base64::engine::general_purpose::STANDARD.encode(&bytes)
     }
 
-    /// Clear any pending deltas (used after initial sync to avoid double-application)
-    fn clear_pending_deltas(&mut self) {
-        self.pending_deltas.lock().clear();
+    /// Create a position-stable cursor at a byte offset of the "content" text.
+    /// Returns the encoded cursor, or None if the position is out of range or a
+    /// branch is open.
+    fn cursor_create(&self, byte_pos: usize) -> Option<Vec<u8>> {
+        if self.branch.is_some() {
+            warn!(
+                "[crdt:{}] Cannot create a cursor with an open branch",
+                self.id
+            );
+            return None;
+        }
+        let text = self.doc.get_text(DEFAULT_CONTAINER);
+        let pos = text.convert_pos(byte_pos, PosType::Bytes, PosType::Unicode)?;
+        text.get_cursor(pos, Side::Middle)
+            .map(|cursor| cursor.encode())
     }
-}
+
+    /// Resolve an encoded cursor to its current byte offset in the "content" text.
+    /// Returns None while a branch is open.
+    fn cursor_resolve(&self, cursor_bytes: &[u8]) -> Option<usize> {
+        if self.branch.is_some() {
+            warn!(
+                "[crdt:{}] Cannot resolve a cursor with an open branch",
+                self.id
+            );
+            return None;
+        }
+        let cursor = match Cursor::decode(cursor_bytes) {
+            Ok(cursor) => cursor,
+            Err(e) => {
+                warn!("[crdt:{}] Failed to decode cursor: {}", self.id, e);
+                return None;
+            }
+        };
+
+        let pos = match self.doc.get_cursor_pos(&cursor) {
+            Ok(result) => result.current.pos,
+            Err(e) => {
+                warn!("[crdt:{}] Failed to resolve cursor: {:?}", self.id, e);
+                return None;
+            }
+        };
+
+        self.doc
+            .get_text(DEFAULT_CONTAINER)
+            .convert_pos(pos, PosType::Unicode, PosType::Bytes)
+    }
+
+    /// Undo the last local edit. The undo is committed as a new change so it
+    /// propagates to peers. Returns false if there was nothing to undo or a
+    /// branch or batch is open.
+    fn undo(&mut self) -> bool {
+        self.run_undo_step(true)
+    }
+
+    /// Redo the last undone local edit. Returns false if there was nothing to redo
+    /// or a branch or batch is open.
+    fn redo(&mut self) -> bool {
+        self.run_undo_step(false)
+    }
+
+    fn run_undo_step(&mut self, undo: bool) -> bool {
+        // The undo manager tracks the main line, not the branch shown in the buffer
+        if self.branch.is_some() {
+            warn!("[crdt:{}] Cannot undo or redo with an open branch", self.id);
+            return false;
+        }
+        // Committing the undo would also commit the half-built batch
+        if self.batch_open {
+            warn!("[crdt:{}] Cannot undo or redo with an open batch", self.id);
+            return false;
+        }
+        self.applying_local = true;
+        self.prepare_local_echo(DEFAULT_CONTAINER);
+        let result = if undo {
+            self.undo.undo()
+        } else {
+            self.undo.redo()
+        };
+        let applied = match result {
+            Ok(applied) => applied,
+            Err(e) => {
+                let action = if undo { "undo" } else { "redo" };
+                error!("[crdt:{}] Failed to {}: {}", self.id, action, e);
+                false
+            }
+        };
+
+        if applied {
+            self.doc.commit();
+            self.last_text = self.get_text(DEFAULT_CONTAINER);
+        }
+        self.applying_local = false;
+        applied
+    }
+
+    /// Greatest common history between our oplog and a remote version vector.
+    /// Version vectors describe causally closed sets of ops, so their meet is the
+    /// per-peer minimum.
+    fn common_ancestor(&self, other: &VersionVector) -> VersionVector {
+        self.version_vector().intersection(other)
+    }
+
+    /// Number of ops in our oplog that a remote at `remote` has not seen
+    fn ops_missing_from(&self, remote: &VersionVector) -> usize {
+        self.version_vector()
+            .iter()
+            .map(|(peer, &end)| {
+                let seen = remote.get(peer).copied().unwrap_or(0);
+                end.saturating_sub(seen) as usize
+            })
+            .sum()
+    }
+
+    /// Revert the document to the state at `vv_b64` by committing a new local change
+    /// that undoes everything after it. The target must be part of our history.
+    /// Refused while a branch or batch is open.
+    fn revert_to_b64(&mut self, vv_b64: &str) -> bool {
+        if self.branch.is_some() || self.batch_open {
+            warn!(
+                "[crdt:{}] Cannot revert with an open branch or batch",
+                self.id
+            );
+            return false;
+        }
+        let Some(target) = self.decode_vv_b64(vv_b64) else {
+            return false;
+        };
+
+        if !self.version_vector().includes_vv(&target) {
+            warn!(
+                "[crdt:{}] Cannot revert to a version outside local history",
+                self.id
+            );
+            return false;
+        }
+
+        let frontiers = self.doc.vv_to_frontiers(&target);
+        if let Err(e) = self.doc.revert_to(&frontiers) {
+            error!("[crdt:{}] Failed to revert: {}", self.id, e);
+            return false;
+        }
+
+        self.doc.commit();
+        self.last_text = self.get_text(DEFAULT_CONTAINER);
+        true
+    }
+
+    /// Write a snapshot of the document to `path`.
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let bytes = self
+            .doc
+            .export(ExportMode::Snapshot)
+            .map_err(|e| format!("Failed to export snapshot: {}", e))?;
+        fs::write(path, bytes).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+
+    /// Create a new document from a snapshot file written by `save`.
+    fn load(id: Uuid, path: &Path) -> Result<Self, String> {
+        let bytes =
+            fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+
+        let mut doc = Self::new(id);
+        doc.doc
+            .import(&bytes)
+            .map_err(|e| format!("Failed to import snapshot: {}", e))?;
+        // The loaded content is the starting state, not a remote edit to replay
+        doc.clear_pending_deltas();
+        doc.last_text = doc.get_text(DEFAULT_CONTAINER);
+        Ok(doc)
+    }
+
+    /// Create an independent copy of the main line under a new ID.
+    /// The fork has its own peer ID, subscription and delta queues.
+    fn fork(&self, id: Uuid) -> Result<Self, String> {
+        let bytes = self
+            .doc
+            .export(ExportMode::Snapshot)
+            .map_err(|e| format!("Failed to export snapshot: {}", e))?;
+
+        let mut fork = Self::new(id);
+        fork.doc
+            .import(&bytes)
+            .map_err(|e| format!("Failed to import snapshot: {}", e))?;
+        fork.clear_pending_deltas();
+        fork.last_text = fork.get_text(DEFAULT_CONTAINER);
+        Ok(fork)
+    }
+
+    fn encode_full_state_b64(&self) -> String {
+        // A compacted (shallow) doc has no history to replay, so send its snapshot instead
+        let mode = if self.doc.is_shallow() {
+            ExportMode::Snapshot
+        } else {
+            ExportMode::all_updates()
+        };
+        match self.doc.export(mode) {
+            Ok(bytes) => base64::engine::general_purpose::STANDARD.encode(&bytes),
+            Err(e) => {
+                error!("[crdt:{}] Failed to export full state: {}", self.id, e);
+                String::new()
+            }
+        }
+    }
+
+    /// Replace the LoroDoc with a fresh one holding only the current state, dropping
+    /// the oplog history. The new doc gets a new peer ID, the undo stack is reset,
+    /// and peers older than the compacted version can no longer be sent updates.
+    /// Refused while a branch or batch is open or imports are pending.
+    fn compact(&mut self) -> bool {
+        if self.branch.is_some() || self.batch_open || !self.pending_imports.is_empty() {
+            warn!(
+                "[crdt:{}] Cannot compact with an open branch, batch or pending imports",
+                self.id
+            );
+            return false;
+        }
+
+        let frontiers = self.doc.oplog_frontiers();
+        let bytes = match self.doc.export(ExportMode::shallow_snapshot(&frontiers)) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                error!("[crdt:{}] Failed to export for compaction: {}", self.id, e);
+                return false;
+            }
+        };
+
+        let doc = Self::new_loro_doc();
+        if let Err(e) = doc.import(&bytes) {
+            error!("[crdt:{}] Failed to import compacted state: {}", self.id, e);
+            return false;
+        }
+
+        // Subscribe after importing so the existing content isn't queued as remote deltas
+        self.subscription = Some(Self::setup_subscription(
+            &doc,
+            self.id,
+            Arc::clone(&self.pending_deltas),
+            Arc::clone(&self.checkout_deltas),
+            Arc::clone(&self.overflowed),
+            Arc::clone(&self.echo_local),
+        ));
+        self.undo = UndoManager::new(&doc);
+        self.doc = doc;
+        info!(
+            "[crdt:{}] Compacted to {} bytes, new peer ID {}",
+            self.id,
+            bytes.len(),
+            self.doc.peer_id()
+        );
+        true
+    }
+
+    /// Compute snapshot, update log and text sizes.
+    fn stats(&self) -> DocStats {
+        let export_len = |mode| match self.doc.export(mode) {
+            Ok(bytes) => bytes.len(),
+            Err(e) => {
+                error!("[crdt:{}] Failed to export for stats: {}", self.id, e);
+                0
+            }
+        };
+        let text = self.get_text(DEFAULT_CONTAINER);
+
+        DocStats {
+            snapshot_bytes: export_len(ExportMode::Snapshot),
+            updates_bytes: export_len(ExportMode::all_updates()),
+            text_bytes: text.len(),
+            text_chars: text.chars().count(),
+        }
+    }
+
+    /// Export the whole document state as JSON, e.g. {"content": "Hello"}.
+    fn export_json(&self) -> String {
+        serde_json::to_string(&self.doc.get_deep_value()).unwrap_or_else(|e| {
+            error!("[crdt:{}] Failed to serialize document: {}", self.id, e);
+            "{}".to_string()
+        })
+    }
+
+    /// Rebuild text containers from a JSON object mapping container names to strings.
+    /// Non-string values are skipped. Returns false if the JSON is not an object.
+    fn import_json(&mut self, json: &str) -> bool {
+        let containers: serde_json::Map<String, serde_json::Value> =
+            match serde_json::from_str(json) {
+                Ok(containers) => containers,
+                Err(e) => {
+                    error!("[crdt:{}] Invalid document JSON: {}", self.id, e);
+                    return false;
+                }
+            };
+
+        for (name, value) in containers {
+            match value {
+                serde_json::Value::String(content) => self.set_text(&name, &content),
+                _ => warn!(
+                    "[crdt:{}] Skipping non-text container '{}' in JSON import",
+                    self.id, name
+                ),
+            }
+        }
+        true
+    }
+
+    /// Collect metadata for every change in the oplog, in causal (lamport) order
+    fn changes(&self) -> Vec<ChangeMeta> {
+        let mut changes = Vec::new();
+        for (&peer, &end) in self.version_vector().iter() {
+            let mut counter = 0;
+            while counter < end {
+                let Some(change) = self.doc.get_change(ID::new(peer, counter)) else {
+                    break;
+                };
+                counter = change.id.counter + change.len as Counter;
+                changes.push(change);
+            }
+        }
+        changes.sort();
+        changes
+    }
+
+    /// Change history in causal order, skipping `start` entries and returning at most
+    /// `limit` of them (0 means no limit).
+    fn history(&self, start: usize, limit: usize) -> Vec<HistoryEntry> {
+        let limit = if limit == 0 { usize::MAX } else { limit };
+        self.changes()
+            .into_iter()
+            .skip(start)
+            .take(limit)
+            .map(|change| HistoryEntry {
+                peer: change.id.peer.to_string(),
+                counter_start: change.id.counter,
+                counter_end: change.id.counter + change.len as Counter,
+                lamport: change.lamport,
+                timestamp: change.timestamp,
+                message: change.message.map(|m| m.to_string()),
+            })
+            .collect()
+    }
+
+    /// Replay the history and export a JSON timeline of changes.
+    /// Each entry carries the text as of that change (including its causal history).
+    fn export_transcript(&self) -> String {
+        // One replay doc checked out forward through the sorted changes, so each
+        // step only applies the diff from the previous version
+        let replay = self.doc.fork();
+        let entries: Vec<TranscriptEntry> = self
+            .changes()
+            .into_iter()
+            .map(|change| {
+                let last_id = ID::new(
+                    change.id.peer,
+                    change.id.counter + change.len as Counter - 1,
+                );
+                let text = match replay.checkout(&Frontiers::from_id(last_id)) {
+                    Ok(()) => replay.get_text(DEFAULT_CONTAINER).to_string(),
+                    Err(e) => {
+                        warn!(
+                            "[crdt:{}] Failed to replay change {}: {}",
+                            self.id, last_id, e
+                        );
+                        String::new()
+                    }
+                };
+                TranscriptEntry {
+                    author: change.id.peer.to_string(),
+                    counter: change.id.counter,
+                    lamport: change.lamport,
+                    timestamp: change.timestamp,
+                    message: change.message.map(|m| m.to_string()),
+                    ops: change.len,
+                    text,
+                }
+            })
+            .collect();
+
+        serde_json::to_string(&entries).unwrap_or_else(|e| {
+            error!("[crdt:{}] Failed to serialize transcript: {}", self.id, e);
+            "[]".to_string()
+        })
+    }
+
+    /// Poll for pending TextDelta events from remote updates to the named container
+    fn poll_deltas(&mut self, name: &str) -> Vec<TextDeltaEvent> {
+        // Deltas describe the main line, not the branch shown in the buffer
+        if self.branch.is_some() {
+            return Vec::new();
+        }
+        self.pending_deltas.lock().remove(name).unwrap_or_default()
+    }
+
+    /// If the named container's queue overflowed, drop its remaining deltas and
+    /// return true: they no longer describe a complete change and the caller
+    /// has to resync from the full text.
+    fn take_overflow(&mut self, name: &str) -> bool {
+        if !self.overflowed.lock().remove(name) {
+            return false;
+        }
+        self.pending_deltas.lock().remove(name);
+        if name == DEFAULT_CONTAINER {
+            self.line_base = None;
+        }
+        true
+    }
+
+    /// Poll for pending TextDelta events produced by checkouts of the named container
+    fn poll_checkout_deltas(&mut self, name: &str) -> Vec<TextDeltaEvent> {
+        self.checkout_deltas.lock().remove(name).unwrap_or_default()
+    }
+
+    /// Poll pending deltas of the "content" text as line/column edits
+    fn poll_line_edits(&mut self) -> Vec<LineEdit> {
+        let deltas = self.poll_deltas(DEFAULT_CONTAINER);
+        if deltas.is_empty() {
+            return Vec::new();
+        }
+
+        match self.line_base.take() {
+            Some(base) => deltas_to_line_edits(&base, &deltas),
+            None => {
+                warn!(
+                    "[crdt:{}] Dropping {} deltas with no base text for line conversion",
+                    self.id,
+                    deltas.len()
+                );
+                Vec::new()
+            }
+        }
+    }
+
+    /// Clear any pending deltas (used after initial sync to avoid double-application)
+    fn clear_pending_deltas(&mut self) {
+        self.pending_deltas.lock().clear();
+        self.overflowed.lock().clear();
+    }
+
+    /// Drop pending deltas and return whether there were any, plus the current text.
+    /// Lets callers re-sync the whole buffer instead of applying deltas one by one.
+    fn drain_and_get_text(&mut self, name: &str) -> (bool, String) {
+        let overflowed = self.overflowed.lock().remove(name);
+        let changed = self
+            .pending_deltas
+            .lock()
+            .remove(name)
+            .is_some_and(|deltas| !deltas.is_empty());
+        (changed || overflowed, self.get_text(name))
+    }
+}
 
 // ============================================================================
 // FFI Functions
@@ -350,23 +1553,40 @@ fn doc_create() -> String {
     id.to_string()
 }
 
-/// Destroy a CRDT document.
-fn doc_destroy(doc_id: String) {
-    let id = match Uuid::parse_str(&doc_id) {
-        Ok(id) => id,
+/// Create a new CRDT document with a fixed Loro peer ID (u64 as a decimal string).
+/// Returns doc_id, or empty string if the ID is invalid or already used by another doc.
+fn doc_create_with_peer(peer_id: String) -> String {
+    let peer: u64 = match peer_id.parse() {
+        Ok(peer) => peer,
         Err(e) => {
-            warn!("Invalid doc ID '{}': {}", doc_id, e);
-            return;
+            warn!("Invalid peer ID '{}': {}", peer_id, e);
+            return String::new();
         }
     };
 
-    if DOCS.lock().remove(&id).is_some() {
-        info!("[crdt:{}] Document destroyed", id);
+    let mut docs = DOCS.lock();
+    if docs.values().any(|doc| doc.doc.peer_id() == peer) {
+        warn!("Peer ID {} is already used by another document", peer);
+        return String::new();
+    }
+
+    let id = Uuid::new_v4();
+    let doc = CrdtDoc::new(id);
+    if let Err(e) = doc.doc.set_peer_id(peer) {
+        warn!("[crdt:{}] Failed to set peer ID {}: {}", id, peer, e);
+        return String::new();
     }
+
+    info!("[crdt:{}] Document created with peer ID {}", id, peer);
+    docs.insert(id, doc);
+
+    id.to_string()
 }
 
-/// Get the full text content of a document.
-fn doc_get_text(doc_id: String) -> String {
+/// Create an independent copy of a document for scratch editing.
+/// Edits to the fork never reach the original or its peers.
+/// Returns the new doc_id, or empty string on error.
+fn doc_fork(doc_id: String) -> String {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
@@ -375,98 +1595,112 @@ fn doc_get_text(doc_id: String) -> String {
         }
     };
 
-    let docs = DOCS.lock();
-    if let Some(doc) = docs.get(&id) {
-        doc.get_text()
-    } else {
+    let mut docs = DOCS.lock();
+    let Some(doc) = docs.get(&id) else {
         warn!("[crdt:{}] Document not found", id);
-        String::new()
+        return String::new();
+    };
+
+    let fork_id = Uuid::new_v4();
+    match doc.fork(fork_id) {
+        Ok(fork) => {
+            info!("[crdt:{}] Forked into {}", id, fork_id);
+            docs.insert(fork_id, fork);
+            fork_id.to_string()
+        }
+        Err(e) => {
+            error!("[crdt:{}] Failed to fork: {}", id, e);
+            String::new()
+        }
     }
 }
 
-/// Set the full text content of a document (replaces everything).
-fn doc_set_text((doc_id, content): (String, String)) {
+/// Save a snapshot of a document to a file. Returns true on success.
+fn doc_save((doc_id, path): (String, String)) -> bool {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
             warn!("Invalid doc ID '{}': {}", doc_id, e);
-            return;
+            return false;
         }
     };
 
-    let mut docs = DOCS.lock();
-    if let Some(doc) = docs.get_mut(&id) {
-        doc.set_text(&content);
-        debug!("[crdt:{}] Set text ({} bytes)", id, content.len());
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        match doc.save(Path::new(&path)) {
+            Ok(()) => {
+                info!("[crdt:{}] Saved snapshot to {}", id, path);
+                true
+            }
+            Err(e) => {
+                error!("[crdt:{}] {}", id, e);
+                false
+            }
+        }
     } else {
         warn!("[crdt:{}] Document not found", id);
+        false
     }
 }
 
-/// Apply a local edit to the document.
-/// Args: (doc_id, start_byte, end_byte, new_text)
-fn doc_apply_edit((doc_id, start_byte, end_byte, new_text): (String, usize, usize, String)) {
-    let id = match Uuid::parse_str(&doc_id) {
-        Ok(id) => id,
+/// Load a snapshot file into a new document. Returns the new doc_id, or empty string on error.
+fn doc_load(path: String) -> String {
+    let id = Uuid::new_v4();
+    match CrdtDoc::load(id, Path::new(&path)) {
+        Ok(doc) => {
+            info!("[crdt:{}] Document loaded from {}", id, path);
+            DOCS.lock().insert(id, doc);
+            id.to_string()
+        }
         Err(e) => {
-            warn!("Invalid doc ID '{}': {}", doc_id, e);
-            return;
+            error!("[crdt:{}] {}", id, e);
+            String::new()
         }
-    };
-
-    let mut docs = DOCS.lock();
-    if let Some(doc) = docs.get_mut(&id) {
-        debug!(
-            "[crdt:{}] Apply edit: [{}, {}) -> '{}'",
-            id, start_byte, end_byte, new_text
-        );
-        doc.apply_edit(start_byte, end_byte, &new_text);
-    } else {
-        warn!("[crdt:{}] Document not found", id);
     }
 }
 
-/// Get the version vector as base64.
-fn doc_state_vector(doc_id: String) -> String {
+/// Destroy a CRDT document.
+fn doc_destroy(doc_id: String) {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
             warn!("Invalid doc ID '{}': {}", doc_id, e);
-            return String::new();
+            return;
         }
     };
 
-    let docs = DOCS.lock();
-    if let Some(doc) = docs.get(&id) {
-        doc.version_vector_b64()
-    } else {
-        warn!("[crdt:{}] Document not found", id);
-        String::new()
+    if DOCS.lock().remove(&id).is_some() {
+        info!("[crdt:{}] Document destroyed", id);
     }
 }
 
-/// Apply a remote update (base64-encoded).
-fn doc_apply_update((doc_id, update_b64): (String, String)) -> bool {
-    let id = match Uuid::parse_str(&doc_id) {
-        Ok(id) => id,
-        Err(e) => {
-            warn!("Invalid doc ID '{}': {}", doc_id, e);
-            return false;
-        }
-    };
+/// Remove every document from `registry`, dropping them (and their subscriptions)
+/// after the lock is released. Returns the number removed.
+fn destroy_all_in(registry: &Mutex<HashMap<Uuid, CrdtDoc>>) -> usize {
+    let docs: Vec<CrdtDoc> = registry.lock().drain().map(|(_, doc)| doc).collect();
+    docs.len()
+}
 
-    let mut docs = DOCS.lock();
-    if let Some(doc) = docs.get_mut(&id) {
-        debug!("[crdt:{}] Applying remote update", id);
-        doc.apply_update_b64(&update_b64)
-    } else {
-        warn!("[crdt:{}] Document not found", id);
-        false
+/// Destroy every document. Intended for VimLeavePre / plugin reload; safe to
+/// call when no documents exist. Returns the number destroyed.
+fn doc_destroy_all() -> usize {
+    let count = destroy_all_in(&DOCS);
+    if count > 0 {
+        info!("[crdt] Destroyed {} document(s)", count);
     }
+    count
 }
 
-/// Encode update diff from remote version vector (both base64).
-fn doc_encode_update((doc_id, remote_vv_b64): (String, String)) -> String {
+/// IDs of every live document as a JSON array, for finding leaked documents.
+fn doc_list() -> String {
+    let mut ids: Vec<String> = DOCS.lock().keys().map(Uuid::to_string).collect();
+    ids.sort();
+    serde_json::to_string(&ids).unwrap_or_else(|_| "[]".to_string())
+}
+
+/// Get the full text content of a container ("" for the default "content").
+/// Args: (doc_id, container)
+fn doc_get_text((doc_id, container): (String, String)) -> String {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
@@ -477,15 +1711,16 @@ fn doc_encode_update((doc_id, remote_vv_b64): (String, String)) -> String {
 
     let docs = DOCS.lock();
     if let Some(doc) = docs.get(&id) {
-        doc.encode_update_b64(&remote_vv_b64)
+        doc.get_text(container_name(&container))
     } else {
         warn!("[crdt:{}] Document not found", id);
         String::new()
     }
 }
 
-/// Encode full document state as base64 update.
-fn doc_encode_full_state(doc_id: String) -> String {
+/// Get the text between two byte offsets of a document.
+/// Args: (doc_id, start_byte, end_byte)
+fn doc_get_text_range((doc_id, start_byte, end_byte): (String, usize, usize)) -> String {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
@@ -496,40 +1731,1026 @@ fn doc_encode_full_state(doc_id: String) -> String {
 
     let docs = DOCS.lock();
     if let Some(doc) = docs.get(&id) {
-        doc.encode_full_state_b64()
+        doc.get_text_range(start_byte, end_byte)
     } else {
         warn!("[crdt:{}] Document not found", id);
         String::new()
     }
 }
 
-/// Poll for pending TextDelta events from remote updates.
-/// Returns list of delta events as JSON strings.
-/// Format: {"type":"retain"|"insert"|"delete", "len":N} or {"type":"insert", "text":"..."}
-fn doc_poll_deltas(doc_id: String) -> Vec<String> {
+/// Set the full text content of a container (replaces everything).
+/// Args: (doc_id, container, content)
+fn doc_set_text((doc_id, container, content): (String, String, String)) {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
             warn!("Invalid doc ID '{}': {}", doc_id, e);
-            return Vec::new();
+            return;
         }
     };
 
     let mut docs = DOCS.lock();
     if let Some(doc) = docs.get_mut(&id) {
-        let deltas = doc.poll_deltas();
-        if !deltas.is_empty() {
-            debug!("[crdt:{}] Polling {} deltas", id, deltas.len());
-        }
-        deltas.into_iter().map(|d| d.to_json()).collect()
+        let name = container_name(&container);
+        doc.set_text(name, &content);
+        debug!(
+            "[crdt:{}] Set text of '{}' ({} bytes)",
+            id,
+            name,
+            content.len()
+        );
     } else {
-        Vec::new()
+        warn!("[crdt:{}] Document not found", id);
     }
 }
 
-/// Clear any pending deltas.
-/// Call this after initial sync to avoid double-application of the snapshot.
-fn doc_clear_deltas(doc_id: String) {
+/// Apply a local edit to a container of the document.
+/// Args: (doc_id, container, start_byte, end_byte, new_text)
+fn doc_apply_edit(
+    (doc_id, container, start_byte, end_byte, new_text): (String, String, usize, usize, String),
+) {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        let name = container_name(&container);
+        debug!(
+            "[crdt:{}] Apply edit to '{}': [{}, {}) -> '{}'",
+            id, name, start_byte, end_byte, new_text
+        );
+        doc.apply_edit(name, start_byte, end_byte, &new_text);
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+    }
+}
+
+/// Open a batch so that following doc_apply_edit calls produce a single commit.
+/// Returns false if a batch was already open.
+fn doc_begin_batch(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::begin_batch)
+}
+
+/// Commit the edits collected since doc_begin_batch.
+/// Returns false if no batch was open.
+fn doc_commit_batch(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::commit_batch)
+}
+
+/// Apply a local edit identified by `edit_id`, ignoring duplicates.
+/// Args: (doc_id, edit_id, start_byte, end_byte, new_text)
+/// Returns true if applied, false if the edit was a duplicate or the doc was not found.
+fn doc_apply_edit_once(
+    (doc_id, edit_id, start_byte, end_byte, new_text): (String, String, usize, usize, String),
+) -> bool {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        let applied = doc.apply_edit_once(&edit_id, start_byte, end_byte, &new_text);
+        if !applied {
+            debug!("[crdt:{}] Ignoring duplicate edit {}", id, edit_id);
+        }
+        applied
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Mark a byte range of the document with a formatting attribute.
+/// Args: (doc_id, start_byte, end_byte, key, value_json)
+/// Returns true on success.
+fn doc_mark(
+    (doc_id, start_byte, end_byte, key, value_json): (String, usize, usize, String, String),
+) -> bool {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let value: LoroValue = match serde_json::from_str(&value_json) {
+        Ok(value) => value,
+        Err(e) => {
+            warn!("[crdt:{}] Invalid mark value '{}': {}", id, value_json, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        debug!(
+            "[crdt:{}] Mark [{}, {}) {}={}",
+            id, start_byte, end_byte, key, value_json
+        );
+        doc.mark(start_byte, end_byte, &key, value)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Remove a formatting attribute from a byte range of the document.
+/// Args: (doc_id, start_byte, end_byte, key)
+/// Returns true on success.
+fn doc_unmark((doc_id, start_byte, end_byte, key): (String, usize, usize, String)) -> bool {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        debug!(
+            "[crdt:{}] Unmark [{}, {}) {}",
+            id, start_byte, end_byte, key
+        );
+        doc.unmark(start_byte, end_byte, &key)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Get the version vector as base64.
+fn doc_state_vector(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.version_vector_b64()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Get the oplog frontiers (base64), a compact alternative to the state vector.
+fn doc_frontiers(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.frontiers_b64()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Export updates made since the given frontiers (both base64).
+fn doc_export_from_frontiers((doc_id, frontiers_b64): (String, String)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.export_from_frontiers_b64(&frontiers_b64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Apply a remote update (base64-encoded).
+fn doc_apply_update((doc_id, update_b64): (String, String)) -> bool {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        debug!("[crdt:{}] Applying remote update", id);
+        doc.apply_update_b64(&update_b64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Apply a remote update and return the "content" deltas produced by that import
+/// alone, in the doc_poll_deltas JSON format. Deltas from earlier imports stay
+/// queued for doc_poll_deltas. Returns an empty list if nothing changed or on error.
+fn doc_apply_update_returning_deltas((doc_id, update_b64): (String, String)) -> Vec<String> {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return Vec::new();
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        let (outcome, deltas) = doc.import_update_capturing(&update_b64);
+        debug!(
+            "[crdt:{}] Import {} with {} deltas",
+            id,
+            outcome.as_str(),
+            deltas.len()
+        );
+        deltas.into_iter().map(|d| d.to_json()).collect()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        Vec::new()
+    }
+}
+
+/// Apply several remote updates in order under a single lock.
+/// Args: (doc_id, JSON array of base64 updates)
+/// Each update goes through the same bookkeeping as doc_apply_update.
+/// Returns how many were applied (including ones left pending on dependencies).
+fn doc_apply_updates((doc_id, updates_json): (String, String)) -> usize {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return 0;
+        }
+    };
+
+    let updates: Vec<String> = match serde_json::from_str(&updates_json) {
+        Ok(updates) => updates,
+        Err(e) => {
+            warn!("[crdt:{}] Invalid updates array: {}", id, e);
+            return 0;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        debug!("[crdt:{}] Applying {} remote updates", id, updates.len());
+        updates
+            .iter()
+            .filter(|update| doc.apply_update_b64(update))
+            .count()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        0
+    }
+}
+
+/// Apply a remote update (base64-encoded) and report the outcome.
+/// Returns "applied", "pending" (waiting on missing dependencies), "too_large"
+/// (rejected by the size limit) or "failed".
+fn doc_apply_update_status((doc_id, update_b64): (String, String)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return ImportOutcome::Failed.as_str().to_string();
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        debug!("[crdt:{}] Applying remote update", id);
+        doc.import_update_b64(&update_b64).as_str().to_string()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        ImportOutcome::Failed.as_str().to_string()
+    }
+}
+
+/// Check whether an update (base64) would be accepted without applying it.
+/// Returns JSON: {"valid":bool, "new_size":N, "error":string|null}, where
+/// new_size is the "content" text length in bytes after the import.
+fn doc_validate_update((doc_id, update_b64): (String, String)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        let result = doc.validate_update_b64(&update_b64);
+        serde_json::to_string(&result).unwrap_or_else(|e| {
+            error!("[crdt:{}] Failed to serialize validation: {}", id, e);
+            String::new()
+        })
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Get the number of imported updates waiting on missing dependencies.
+fn doc_pending_count(doc_id: String) -> usize {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return 0;
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.pending_count()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        0
+    }
+}
+
+/// Get the version vector (base64) that pending updates are waiting on.
+/// Returns empty string if nothing is missing.
+fn doc_missing_deps(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.missing_deps()
+            .map(|vv| base64::engine::general_purpose::STANDARD.encode(vv.encode()))
+            .unwrap_or_default()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Create a stable cursor at a byte position.
+/// Args: (doc_id, byte_pos)
+/// Returns an opaque base64 cursor handle, or empty string on error.
+fn doc_cursor_create((doc_id, byte_pos): (String, usize)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        match doc.cursor_create(byte_pos) {
+            Some(bytes) => base64::engine::general_purpose::STANDARD.encode(bytes),
+            None => {
+                warn!("[crdt:{}] Cannot create cursor at byte {}", id, byte_pos);
+                String::new()
+            }
+        }
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Resolve a cursor handle to its current byte position after applied updates.
+/// Args: (doc_id, cursor_b64)
+/// Returns the byte position, or -1 if the cursor cannot be resolved.
+fn doc_cursor_resolve((doc_id, cursor_b64): (String, String)) -> i64 {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return -1;
+        }
+    };
+
+    let cursor_bytes = match base64::engine::general_purpose::STANDARD.decode(&cursor_b64) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            warn!("[crdt:{}] Failed to decode cursor base64: {}", id, e);
+            return -1;
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.cursor_resolve(&cursor_bytes)
+            .map_or(-1, |pos| pos as i64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        -1
+    }
+}
+
+/// Enable or disable detached editing.
+/// Enabling opens a branch at the current version: local edits go to the branch,
+/// are not sent to peers, and doc_poll_deltas returns nothing while it is open.
+/// Remote updates are still imported into the main line.
+/// Disabling is the same as doc_discard_detached.
+/// Returns true if the mode changed.
+fn doc_set_detached_editing((doc_id, enable): (String, bool)) -> bool {
+    with_doc_mut(&doc_id, |doc| {
+        if enable {
+            doc.start_branch()
+        } else {
+            doc.discard_branch()
+        }
+    })
+}
+
+/// Also queue deltas for local edits, for integrations that show a second view
+/// of the same document. Off by default, since the editing buffer already has
+/// its own edits. Edits on a detached branch are never echoed.
+/// Returns true if the document exists.
+fn doc_set_echo_local((doc_id, enable): (String, bool)) -> bool {
+    with_doc_mut(&doc_id, |doc| {
+        doc.echo_local.store(enable, Ordering::Relaxed);
+        true
+    })
+}
+
+/// Check whether a detached editing branch is open.
+fn doc_is_detached_editing(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, |doc| doc.branch.is_some())
+}
+
+/// Merge the detached branch into the main line. Remote deltas received while the
+/// branch was open are dropped, so callers should re-read the text afterwards.
+/// Returns true on success.
+fn doc_merge_detached(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::merge_branch)
+}
+
+/// Discard the detached branch and return to the main line. Callers should re-read
+/// the text afterwards. Returns true if a branch was open.
+fn doc_discard_detached(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::discard_branch)
+}
+
+/// Check out a past version given its frontiers (base64, see doc_frontiers).
+/// While checked out, remote updates are still imported but not reflected in the
+/// text, and local edits are rejected; call doc_checkout_to_latest before editing.
+/// Returns true on success.
+fn doc_checkout((doc_id, frontiers_b64): (String, String)) -> bool {
+    with_doc_mut(&doc_id, |doc| doc.checkout_b64(&frontiers_b64))
+}
+
+/// Return to the latest version after doc_checkout.
+/// Returns true if the document was checked out to a past version.
+fn doc_checkout_to_latest(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::checkout_to_latest)
+}
+
+/// Convert a UTF-16 code unit offset in the "content" text to a byte offset.
+/// Args: (doc_id, utf16_offset)
+/// Returns -1 if the offset is out of range or splits a surrogate pair.
+fn doc_utf16_to_byte((doc_id, utf16_offset): (String, usize)) -> i64 {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return -1;
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        utf16_to_byte(&doc.get_text(DEFAULT_CONTAINER), utf16_offset).map_or(-1, |pos| pos as i64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        -1
+    }
+}
+
+/// Convert a byte offset in the "content" text to a UTF-16 code unit offset.
+/// Args: (doc_id, byte_offset)
+/// Returns -1 if the offset is out of range or inside a multi-byte character.
+fn doc_byte_to_utf16((doc_id, byte_offset): (String, usize)) -> i64 {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return -1;
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        byte_to_utf16(&doc.get_text(DEFAULT_CONTAINER), byte_offset).map_or(-1, |pos| pos as i64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        -1
+    }
+}
+
+/// Undo the last local edit.
+/// Undo is a local change, so it is not reported by doc_poll_deltas;
+/// callers should re-read the text afterwards.
+/// Returns true if an edit was undone.
+fn doc_undo(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::undo)
+}
+
+/// Redo the last undone local edit. Returns true if an edit was redone.
+fn doc_redo(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::redo)
+}
+
+/// Check whether there is a local edit to undo.
+fn doc_can_undo(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, |doc| doc.undo.can_undo())
+}
+
+/// Check whether there is an undone edit to redo.
+fn doc_can_redo(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, |doc| doc.undo.can_redo())
+}
+
+/// Look up a document and run a boolean operation on it (false if not found).
+fn with_doc_mut(doc_id: &str, f: impl FnOnce(&mut CrdtDoc) -> bool) -> bool {
+    let id = match Uuid::parse_str(doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        f(doc)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Compute the common ancestor of the local doc and a remote version vector.
+/// Args: (doc_id, other_vv_b64)
+/// Returns the ancestor version vector as base64, or empty string on error.
+fn doc_common_ancestor((doc_id, other_vv_b64): (String, String)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.decode_vv_b64(&other_vv_b64)
+            .map(|other| {
+                base64::engine::general_purpose::STANDARD
+                    .encode(doc.common_ancestor(&other).encode())
+            })
+            .unwrap_or_default()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Count the ops the local doc has that a remote version vector (base64) lacks,
+/// e.g. to show "2 local changes not yet synced".
+/// Args: (doc_id, remote_vv_b64)
+/// Returns -1 on error.
+fn doc_missing_from((doc_id, remote_vv_b64): (String, String)) -> i64 {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return -1;
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.decode_vv_b64(&remote_vv_b64)
+            .map_or(-1, |remote| doc.ops_missing_from(&remote) as i64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        -1
+    }
+}
+
+/// Check whether the local doc has changes a remote version vector (base64) lacks.
+/// Args: (doc_id, remote_vv_b64)
+fn doc_is_ahead(args: (String, String)) -> bool {
+    doc_missing_from(args) > 0
+}
+
+/// Revert the document to a previous version vector (base64), e.g. the last
+/// version acknowledged by the server after an update was rejected.
+/// The revert is a new local change, so it propagates via doc_encode_update.
+/// Returns true on success.
+fn doc_revert_to((doc_id, vv_b64): (String, String)) -> bool {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        info!("[crdt:{}] Reverting to earlier version", id);
+        doc.revert_to_b64(&vv_b64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Encode update diff from remote version vector (both base64).
+fn doc_encode_update((doc_id, remote_vv_b64): (String, String)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.encode_update_b64(&remote_vv_b64)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Export the updates `to_doc_id` has that `from_doc_id` is missing (base64).
+fn doc_diff((from_doc_id, to_doc_id): (String, String)) -> String {
+    let from_id = match Uuid::parse_str(&from_doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", from_doc_id, e);
+            return String::new();
+        }
+    };
+    let to_id = match Uuid::parse_str(&to_doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", to_doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    let Some(from) = docs.get(&from_id) else {
+        warn!("[crdt:{}] Document not found", from_id);
+        return String::new();
+    };
+    let Some(to) = docs.get(&to_id) else {
+        warn!("[crdt:{}] Document not found", to_id);
+        return String::new();
+    };
+    to.export_updates_b64(&from.version_vector())
+}
+
+/// Import everything `source_doc_id` has that `target_doc_id` is missing into the
+/// target. Deltas are queued on the target as for any remote update.
+/// Returns true if the target changed.
+fn doc_merge((target_doc_id, source_doc_id): (String, String)) -> bool {
+    let target_id = match Uuid::parse_str(&target_doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", target_doc_id, e);
+            return false;
+        }
+    };
+    let source_id = match Uuid::parse_str(&source_doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", source_doc_id, e);
+            return false;
+        }
+    };
+    if target_id == source_id {
+        warn!("[crdt:{}] Cannot merge a document into itself", target_id);
+        return false;
+    }
+
+    let mut docs = DOCS.lock();
+    let Some(target_vv) = docs.get(&target_id).map(CrdtDoc::version_vector) else {
+        warn!("[crdt:{}] Document not found", target_id);
+        return false;
+    };
+    let Some(update) = docs
+        .get(&source_id)
+        .map(|source| source.export_updates(&target_vv))
+    else {
+        warn!("[crdt:{}] Document not found", source_id);
+        return false;
+    };
+    if update.is_empty() {
+        return false;
+    }
+
+    let Some(target) = docs.get_mut(&target_id) else {
+        return false;
+    };
+    target.import_update_bytes(&update) != ImportOutcome::Failed
+        && target.version_vector() != target_vv
+}
+
+/// Encode full document state as base64 update.
+fn doc_encode_full_state(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.encode_full_state_b64()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Compact a document by dropping its edit history while keeping the content.
+/// The doc keeps its doc_id but gets a new Loro peer ID, and its undo stack is reset.
+/// Returns true on success.
+fn doc_compact(doc_id: String) -> bool {
+    with_doc_mut(&doc_id, CrdtDoc::compact)
+}
+
+/// Get size statistics for a document as JSON:
+/// {"snapshot_bytes","updates_bytes","text_bytes","text_chars"}
+fn doc_stats(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        serde_json::to_string(&doc.stats()).unwrap_or_else(|e| {
+            error!("[crdt:{}] Failed to serialize stats: {}", id, e);
+            String::new()
+        })
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Export the whole document state as a JSON string.
+fn doc_export_json(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.export_json()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Check whether a root text container exists without creating it.
+/// Args: (doc_id, container)
+fn doc_has_container((doc_id, container): (String, String)) -> bool {
+    with_doc_mut(&doc_id, |doc| doc.has_container(container_name(&container)))
+}
+
+/// List the root containers as a JSON array of {"name":"content","type":"Text"}.
+/// Returns empty string on error.
+fn doc_list_containers(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return String::new();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        serde_json::to_string(&doc.list_containers()).unwrap_or_else(|e| {
+            error!("[crdt:{}] Failed to serialize containers: {}", id, e);
+            String::new()
+        })
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        String::new()
+    }
+}
+
+/// Rebuild text content from a JSON object of {container_name: text}.
+/// Returns true on success.
+fn doc_import_json((doc_id, json): (String, String)) -> bool {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return false;
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        doc.import_json(&json)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        false
+    }
+}
+
+/// Get the change history as a JSON array.
+/// Args: (doc_id, start, limit) - skip `start` entries, return at most `limit` (0 = all)
+/// Entry format: {"peer","counter_start","counter_end","lamport","timestamp","message"}
+fn doc_get_history((doc_id, start, limit): (String, usize, usize)) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return "[]".to_string();
+        }
+    };
+
+    // Only collect under the lock; serialize after releasing it
+    let entries = {
+        let docs = DOCS.lock();
+        match docs.get(&id) {
+            Some(doc) => doc.history(start, limit),
+            None => {
+                warn!("[crdt:{}] Document not found", id);
+                return "[]".to_string();
+            }
+        }
+    };
+
+    serde_json::to_string(&entries).unwrap_or_else(|e| {
+        error!("[crdt:{}] Failed to serialize history: {}", id, e);
+        "[]".to_string()
+    })
+}
+
+/// Export a JSON timeline of every change with author, timestamp and text state.
+fn doc_export_transcript(doc_id: String) -> String {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return "[]".to_string();
+        }
+    };
+
+    let docs = DOCS.lock();
+    if let Some(doc) = docs.get(&id) {
+        doc.export_transcript()
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        "[]".to_string()
+    }
+}
+
+/// Poll for pending TextDelta events from remote updates to a container.
+/// Args: (doc_id, container)
+/// Returns list of delta events as JSON strings.
+/// Format: {"type":"retain"|"insert"|"delete", "len":N} or {"type":"insert", "text":"..."}
+/// Retain and insert events carry an "attributes" object when formatting changed.
+/// If the queue overflowed, a single {"type":"overflow"} entry is returned instead
+/// and the buffer must be resynced from doc_get_text.
+fn doc_poll_deltas((doc_id, container): (String, String)) -> Vec<String> {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return Vec::new();
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        let name = container_name(&container);
+        if doc.take_overflow(name) {
+            warn!(
+                "[crdt:{}] Deltas for '{}' overflowed, full resync needed",
+                id, name
+            );
+            return vec![OVERFLOW_SENTINEL.to_string()];
+        }
+        let deltas = doc.poll_deltas(name);
+        if !deltas.is_empty() {
+            debug!(
+                "[crdt:{}] Polling {} deltas for '{}'",
+                id,
+                deltas.len(),
+                name
+            );
+        }
+        deltas.into_iter().map(|d| d.to_json()).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Poll for pending TextDelta events produced by doc_checkout/doc_checkout_to_latest.
+/// Args: (doc_id, container)
+/// Returns the same JSON format as doc_poll_deltas. Checkouts never show up there,
+/// so callers time-travelling a buffer must poll both.
+fn doc_poll_checkout_deltas((doc_id, container): (String, String)) -> Vec<String> {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return Vec::new();
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        doc.poll_checkout_deltas(container_name(&container))
+            .into_iter()
+            .map(|d| d.to_json())
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Poll for pending remote changes to the "content" text as line-oriented edits.
+/// Returns list of JSON strings suitable for nvim_buf_set_text, applied in order:
+/// {"start_row":R,"start_col":C,"end_row":R,"end_col":C,"text":"..."}
+/// Rows are 0-based and columns are byte offsets. Use either this or
+/// doc_poll_deltas for a document, not both. Overflow is reported as in doc_poll_deltas.
+fn doc_poll_deltas_lines(doc_id: String) -> Vec<String> {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return Vec::new();
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        if doc.take_overflow(DEFAULT_CONTAINER) {
+            warn!("[crdt:{}] Line edits overflowed, full resync needed", id);
+            return vec![OVERFLOW_SENTINEL.to_string()];
+        }
+        doc.poll_line_edits()
+            .iter()
+            .filter_map(|edit| serde_json::to_string(edit).ok())
+            .collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// Clear any pending deltas.
+/// Call this after initial sync to avoid double-application of the snapshot.
+fn doc_clear_deltas(doc_id: String) {
     let id = match Uuid::parse_str(&doc_id) {
         Ok(id) => id,
         Err(e) => {
@@ -543,191 +2764,1738 @@ fn doc_clear_deltas(doc_id: String) {
         doc.clear_pending_deltas();
         debug!("[crdt:{}] Cleared pending deltas", id);
     }
-}
+}
+
+/// Clear pending deltas and return (changed, text) in one locked call.
+/// `changed` is true if any remote deltas were pending.
+fn doc_drain_and_get_text(doc_id: String) -> (bool, String) {
+    let id = match Uuid::parse_str(&doc_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid doc ID '{}': {}", doc_id, e);
+            return (false, String::new());
+        }
+    };
+
+    let mut docs = DOCS.lock();
+    if let Some(doc) = docs.get_mut(&id) {
+        doc.drain_and_get_text(DEFAULT_CONTAINER)
+    } else {
+        warn!("[crdt:{}] Document not found", id);
+        (false, String::new())
+    }
+}
+
+/// Set the maximum size in bytes of base64 payloads accepted from Lua.
+/// Applies to doc_apply_update and the Iroh send functions, and caps frames read
+/// from peers unless iroh_set_max_frame_size overrides it. 0 disables the limit.
+fn crdt_set_max_update_size(max_bytes: usize) {
+    MAX_UPDATE_B64_LEN.store(max_bytes, Ordering::Relaxed);
+    info!("Max update size set to {} bytes", max_bytes);
+}
+
+/// Set the maximum number of remote deltas queued per container between polls.
+/// When exceeded the oldest are dropped and the next poll returns a single
+/// {"type":"overflow"} entry. 0 disables the cap.
+fn crdt_set_max_pending_deltas(max_deltas: usize) {
+    MAX_PENDING_DELTAS.store(max_deltas, Ordering::Relaxed);
+    info!("Max pending deltas set to {}", max_deltas);
+}
+
+/// Version of the Loro library this FFI was built against.
+/// Peers can compare this to detect incompatible encodings before importing.
+fn crdt_loro_version() -> String {
+    LORO_VERSION.trim().to_string()
+}
+
+/// CRDT FFI module
+pub fn crdt_ffi() -> Dictionary {
+    Dictionary::from_iter([
+        (
+            "loro_version",
+            Object::from(Function::<(), String>::from_fn(
+                |_| -> Result<String, nvim_oxi::Error> { Ok(crdt_loro_version()) },
+            )),
+        ),
+        (
+            "set_max_pending_deltas",
+            Object::from(Function::<usize, ()>::from_fn(
+                |max_deltas| -> Result<(), nvim_oxi::Error> {
+                    crdt_set_max_pending_deltas(max_deltas);
+                    Ok(())
+                },
+            )),
+        ),
+        (
+            "set_max_update_size",
+            Object::from(Function::<usize, ()>::from_fn(
+                |max_bytes| -> Result<(), nvim_oxi::Error> {
+                    crdt_set_max_update_size(max_bytes);
+                    Ok(())
+                },
+            )),
+        ),
+        (
+            "doc_create",
+            Object::from(Function::<(), String>::from_fn(
+                |_| -> Result<String, nvim_oxi::Error> { Ok(doc_create()) },
+            )),
+        ),
+        (
+            "doc_create_with_peer",
+            Object::from(Function::<String, String>::from_fn(
+                |peer_id| -> Result<String, nvim_oxi::Error> { Ok(doc_create_with_peer(peer_id)) },
+            )),
+        ),
+        (
+            "doc_save",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_save(args)) },
+            )),
+        ),
+        (
+            "doc_load",
+            Object::from(Function::<String, String>::from_fn(
+                |path| -> Result<String, nvim_oxi::Error> { Ok(doc_load(path)) },
+            )),
+        ),
+        (
+            "doc_destroy_all",
+            Object::from(Function::<(), usize>::from_fn(
+                |_| -> Result<usize, nvim_oxi::Error> { Ok(doc_destroy_all()) },
+            )),
+        ),
+        (
+            "doc_list",
+            Object::from(Function::<(), String>::from_fn(
+                |_| -> Result<String, nvim_oxi::Error> { Ok(doc_list()) },
+            )),
+        ),
+        (
+            "doc_destroy",
+            Object::from(Function::<String, ()>::from_fn(
+                |id| -> Result<(), nvim_oxi::Error> {
+                    doc_destroy(id);
+                    Ok(())
+                },
+            )),
+        ),
+        (
+            "doc_get_text",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_get_text(args)) },
+            )),
+        ),
+        (
+            "doc_get_text_range",
+            Object::from(Function::<(String, usize, usize), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_get_text_range(args)) },
+            )),
+        ),
+        (
+            "doc_set_text",
+            Object::from(Function::<(String, String, String), ()>::from_fn(
+                |args| -> Result<(), nvim_oxi::Error> {
+                    doc_set_text(args);
+                    Ok(())
+                },
+            )),
+        ),
+        (
+            "doc_apply_edit",
+            Object::from(
+                Function::<(String, String, usize, usize, String), ()>::from_fn(
+                    |args| -> Result<(), nvim_oxi::Error> {
+                        doc_apply_edit(args);
+                        Ok(())
+                    },
+                ),
+            ),
+        ),
+        (
+            "doc_begin_batch",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_begin_batch(id)) },
+            )),
+        ),
+        (
+            "doc_commit_batch",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_commit_batch(id)) },
+            )),
+        ),
+        (
+            "doc_apply_edit_once",
+            Object::from(
+                Function::<(String, String, usize, usize, String), bool>::from_fn(
+                    |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_apply_edit_once(args)) },
+                ),
+            ),
+        ),
+        (
+            "doc_mark",
+            Object::from(
+                Function::<(String, usize, usize, String, String), bool>::from_fn(
+                    |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_mark(args)) },
+                ),
+            ),
+        ),
+        (
+            "doc_unmark",
+            Object::from(Function::<(String, usize, usize, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_unmark(args)) },
+            )),
+        ),
+        (
+            "doc_state_vector",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_state_vector(id)) },
+            )),
+        ),
+        (
+            "doc_frontiers",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_frontiers(id)) },
+            )),
+        ),
+        (
+            "doc_export_from_frontiers",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_export_from_frontiers(args)) },
+            )),
+        ),
+        (
+            "doc_apply_update",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_apply_update(args)) },
+            )),
+        ),
+        (
+            "doc_apply_update_returning_deltas",
+            Object::from(Function::<(String, String), Vec<String>>::from_fn(
+                |args| -> Result<Vec<String>, nvim_oxi::Error> {
+                    Ok(doc_apply_update_returning_deltas(args))
+                },
+            )),
+        ),
+        (
+            "doc_validate_update",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_validate_update(args)) },
+            )),
+        ),
+        (
+            "doc_apply_updates",
+            Object::from(Function::<(String, String), usize>::from_fn(
+                |args| -> Result<usize, nvim_oxi::Error> { Ok(doc_apply_updates(args)) },
+            )),
+        ),
+        (
+            "doc_apply_update_status",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_apply_update_status(args)) },
+            )),
+        ),
+        (
+            "doc_pending_count",
+            Object::from(Function::<String, usize>::from_fn(
+                |id| -> Result<usize, nvim_oxi::Error> { Ok(doc_pending_count(id)) },
+            )),
+        ),
+        (
+            "doc_missing_deps",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_missing_deps(id)) },
+            )),
+        ),
+        (
+            "doc_cursor_create",
+            Object::from(Function::<(String, usize), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_cursor_create(args)) },
+            )),
+        ),
+        (
+            "doc_cursor_resolve",
+            Object::from(Function::<(String, String), i64>::from_fn(
+                |args| -> Result<i64, nvim_oxi::Error> { Ok(doc_cursor_resolve(args)) },
+            )),
+        ),
+        (
+            "doc_utf16_to_byte",
+            Object::from(Function::<(String, usize), i64>::from_fn(
+                |args| -> Result<i64, nvim_oxi::Error> { Ok(doc_utf16_to_byte(args)) },
+            )),
+        ),
+        (
+            "doc_byte_to_utf16",
+            Object::from(Function::<(String, usize), i64>::from_fn(
+                |args| -> Result<i64, nvim_oxi::Error> { Ok(doc_byte_to_utf16(args)) },
+            )),
+        ),
+        (
+            "doc_set_echo_local",
+            Object::from(Function::<(String, bool), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_set_echo_local(args)) },
+            )),
+        ),
+        (
+            "doc_set_detached_editing",
+            Object::from(Function::<(String, bool), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_set_detached_editing(args)) },
+            )),
+        ),
+        (
+            "doc_fork",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_fork(id)) },
+            )),
+        ),
+        (
+            "doc_checkout",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_checkout(args)) },
+            )),
+        ),
+        (
+            "doc_checkout_to_latest",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_checkout_to_latest(id)) },
+            )),
+        ),
+        (
+            "doc_poll_checkout_deltas",
+            Object::from(Function::<(String, String), Vec<String>>::from_fn(
+                |args| -> Result<Vec<String>, nvim_oxi::Error> {
+                    Ok(doc_poll_checkout_deltas(args))
+                },
+            )),
+        ),
+        (
+            "doc_is_detached_editing",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_is_detached_editing(id)) },
+            )),
+        ),
+        (
+            "doc_merge_detached",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_merge_detached(id)) },
+            )),
+        ),
+        (
+            "doc_discard_detached",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_discard_detached(id)) },
+            )),
+        ),
+        (
+            "doc_undo",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_undo(id)) },
+            )),
+        ),
+        (
+            "doc_redo",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_redo(id)) },
+            )),
+        ),
+        (
+            "doc_can_undo",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_can_undo(id)) },
+            )),
+        ),
+        (
+            "doc_can_redo",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_can_redo(id)) },
+            )),
+        ),
+        (
+            "doc_is_ahead",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_is_ahead(args)) },
+            )),
+        ),
+        (
+            "doc_missing_from",
+            Object::from(Function::<(String, String), i64>::from_fn(
+                |args| -> Result<i64, nvim_oxi::Error> { Ok(doc_missing_from(args)) },
+            )),
+        ),
+        (
+            "doc_common_ancestor",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_common_ancestor(args)) },
+            )),
+        ),
+        (
+            "doc_revert_to",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_revert_to(args)) },
+            )),
+        ),
+        (
+            "doc_encode_update",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_encode_update(args)) },
+            )),
+        ),
+        (
+            "doc_merge",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_merge(args)) },
+            )),
+        ),
+        (
+            "doc_diff",
+            Object::from(Function::<(String, String), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_diff(args)) },
+            )),
+        ),
+        (
+            "doc_encode_full_state",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_encode_full_state(id)) },
+            )),
+        ),
+        (
+            "doc_compact",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(doc_compact(id)) },
+            )),
+        ),
+        (
+            "doc_stats",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_stats(id)) },
+            )),
+        ),
+        (
+            "doc_has_container",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_has_container(args)) },
+            )),
+        ),
+        (
+            "doc_list_containers",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_list_containers(id)) },
+            )),
+        ),
+        (
+            "doc_export_json",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_export_json(id)) },
+            )),
+        ),
+        (
+            "doc_import_json",
+            Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_import_json(args)) },
+            )),
+        ),
+        (
+            "doc_get_history",
+            Object::from(Function::<(String, usize, usize), String>::from_fn(
+                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_get_history(args)) },
+            )),
+        ),
+        (
+            "doc_export_transcript",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_export_transcript(id)) },
+            )),
+        ),
+        (
+            "doc_poll_deltas",
+            Object::from(Function::<(String, String), Vec<String>>::from_fn(
+                |args| -> Result<Vec<String>, nvim_oxi::Error> { Ok(doc_poll_deltas(args)) },
+            )),
+        ),
+        (
+            "doc_poll_deltas_lines",
+            Object::from(Function::<String, Vec<String>>::from_fn(
+                |id| -> Result<Vec<String>, nvim_oxi::Error> { Ok(doc_poll_deltas_lines(id)) },
+            )),
+        ),
+        (
+            "doc_clear_deltas",
+            Object::from(Function::<String, ()>::from_fn(
+                |id| -> Result<(), nvim_oxi::Error> {
+                    doc_clear_deltas(id);
+                    Ok(())
+                },
+            )),
+        ),
+        (
+            "doc_drain_and_get_text",
+            Object::from(Function::<String, (bool, String)>::from_fn(
+                |id| -> Result<(bool, String), nvim_oxi::Error> { Ok(doc_drain_and_get_text(id)) },
+            )),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_loro_sync_roundtrip() {
+        // Create doc A with content
+        let doc_a = LoroDoc::new();
+        let text_a = doc_a.get_text("content");
+        text_a.insert_utf8(0, "Hello World").unwrap();
+
+        assert_eq!(text_a.to_string(), "Hello World");
+
+        // Export all updates from A
+        let updates = doc_a
+            .export(ExportMode::all_updates())
+            .expect("export failed");
+        let updates_b64 = base64::engine::general_purpose::STANDARD.encode(&updates);
+
+        println!(
+            "Export size: {} bytes, b64 len: {}",
+            updates.len(),
+            updates_b64.len()
+        );
+
+        // Create doc B and import
+        let doc_b = LoroDoc::new();
+        let updates_decoded = base64::engine::general_purpose::STANDARD
+            .decode(&updates_b64)
+            .expect("decode failed");
+        doc_b.import(&updates_decoded).expect("import failed");
+
+        let text_b = doc_b.get_text("content");
+        assert_eq!(text_b.to_string(), "Hello World");
+    }
+
+    #[test]
+    fn test_textdelta_subscription() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // Create doc A with content
+        let doc_a = LoroDoc::new();
+        let text_a = doc_a.get_text("content");
+        text_a.insert_utf8(0, "Hello").unwrap();
+        doc_a.commit();
+
+        // Export from A
+        let updates_a = doc_a
+            .export(ExportMode::all_updates())
+            .expect("export failed");
+
+        // Create doc B with subscription
+        let doc_b = LoroDoc::new();
+        let delta_count = Arc::new(AtomicUsize::new(0));
+        let delta_count_clone = Arc::clone(&delta_count);
+
+        let _sub = doc_b.subscribe_root(Arc::new(move |event| {
+            if matches!(event.triggered_by, EventTriggerKind::Import) {
+                for diff in &event.events {
+                    if let Diff::Text(deltas) = &diff.diff {
+                        delta_count_clone.fetch_add(deltas.len(), Ordering::SeqCst);
+                    }
+                }
+            }
+        }));
+
+        // Import into B - should trigger subscription
+        doc_b.import(&updates_a).expect("import failed");
+
+        // Verify we got delta events
+        assert!(
+            delta_count.load(Ordering::SeqCst) > 0,
+            "Should have received delta events"
+        );
+
+        let text_b = doc_b.get_text("content");
+        assert_eq!(text_b.to_string(), "Hello");
+    }
 
-/// CRDT FFI module
-pub fn crdt_ffi() -> Dictionary {
-    Dictionary::from_iter([
-        (
-            "doc_create",
-            Object::from(Function::<(), String>::from_fn(
-                |_| -> Result<String, nvim_oxi::Error> { Ok(doc_create()) },
-            )),
-        ),
-        (
-            "doc_destroy",
-            Object::from(Function::<String, ()>::from_fn(
-                |id| -> Result<(), nvim_oxi::Error> {
-                    doc_destroy(id);
-                    Ok(())
+    #[test]
+    fn test_textdelta_event_serialization() {
+        let retain = TextDeltaEvent::Retain {
+            len: 5,
+            attributes: None,
+        };
+        assert_eq!(retain.to_json(), r#"{"type":"retain","len":5}"#);
+
+        let insert = TextDeltaEvent::Insert {
+            text: "hello".to_string(),
+            attributes: None,
+        };
+        assert_eq!(insert.to_json(), r#"{"type":"insert","text":"hello"}"#);
+
+        let delete = TextDeltaEvent::Delete { len: 3 };
+        assert_eq!(delete.to_json(), r#"{"type":"delete","len":3}"#);
+
+        // Test with special characters
+        let insert_special = TextDeltaEvent::Insert {
+            text: "hello\nworld".to_string(),
+            attributes: None,
+        };
+        assert_eq!(
+            insert_special.to_json(),
+            r#"{"type":"insert","text":"hello\nworld"}"#
+        );
+    }
+
+    #[test]
+    fn test_export_transcript() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        assert!(doc_b.apply_update_b64(&doc_a.encode_full_state_b64()));
+        doc_b.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+
+        let transcript: Vec<serde_json::Value> =
+            serde_json::from_str(&doc_b.export_transcript()).expect("valid JSON");
+
+        assert_eq!(transcript.len(), 2);
+        assert_eq!(transcript[0]["text"], "Hello");
+        assert_eq!(transcript[1]["text"], "Hello World");
+        assert_eq!(
+            transcript[0]["author"],
+            doc_a.doc.peer_id().to_string().as_str()
+        );
+        assert_eq!(
+            transcript[1]["author"],
+            doc_b.doc.peer_id().to_string().as_str()
+        );
+        assert!(transcript[1]["timestamp"].as_i64().unwrap() > 0);
+    }
+
+    #[test]
+    fn test_apply_edit_once_ignores_duplicates() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "ab");
+
+        assert!(doc.apply_edit_once("edit-1", 1, 1, "x"));
+        assert!(!doc.apply_edit_once("edit-1", 1, 1, "x"));
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "axb");
+
+        assert!(doc.apply_edit_once("edit-2", 1, 1, "x"));
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "axxb");
+    }
+
+    #[test]
+    fn test_apply_edit_once_evicts_oldest() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+
+        for i in 0..=MAX_RECENT_EDIT_IDS {
+            assert!(doc.apply_edit_once(&format!("edit-{i}"), 0, 0, "x"));
+        }
+        assert_eq!(doc.recent_edit_ids.len(), MAX_RECENT_EDIT_IDS);
+
+        // The first ID has been evicted, so it's accepted again
+        assert!(doc.apply_edit_once("edit-0", 0, 0, "x"));
+    }
+
+    #[test]
+    fn test_loro_version() {
+        let version = crdt_loro_version();
+        assert!(!version.is_empty());
+        assert!(!version.ends_with('\n'));
+        assert_eq!(version.split('.').count(), 3);
+    }
+
+    #[test]
+    fn test_get_text_range() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        assert_eq!(doc.get_text_range(0, 10), "");
+
+        doc.set_text(DEFAULT_CONTAINER, "Hello World");
+        assert_eq!(doc.get_text_range(0, 5), "Hello");
+        assert_eq!(doc.get_text_range(6, 11), "World");
+
+        // Out-of-range and inverted ranges are clamped
+        assert_eq!(doc.get_text_range(6, 100), "World");
+        assert_eq!(doc.get_text_range(100, 200), "");
+        assert_eq!(doc.get_text_range(5, 2), "");
+    }
+
+    #[test]
+    fn test_get_text_range_multibyte() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "héllo wörld");
+
+        // "é" and "ö" are two bytes each
+        assert_eq!(doc.get_text_range(0, 6), "héllo");
+        assert_eq!(doc.get_text_range(7, 13), "wörld");
+    }
+
+    #[test]
+    fn test_import_update_capturing() {
+        let mut source = CrdtDoc::new(Uuid::new_v4());
+        source.set_text(DEFAULT_CONTAINER, "Hello");
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.apply_update_b64(&source.encode_full_state_b64());
+
+        let vv = source.version_vector_b64();
+        source.apply_edit(DEFAULT_CONTAINER, 5, 5, "!");
+        let (outcome, deltas) = doc.import_update_capturing(&source.encode_update_b64(&vv));
+        assert_eq!(outcome, ImportOutcome::Applied);
+        assert_eq!(
+            deltas,
+            vec![
+                TextDeltaEvent::Retain {
+                    len: 5,
+                    attributes: None,
                 },
-            )),
-        ),
-        (
-            "doc_get_text",
-            Object::from(Function::<String, String>::from_fn(
-                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_get_text(id)) },
-            )),
-        ),
-        (
-            "doc_set_text",
-            Object::from(Function::<(String, String), ()>::from_fn(
-                |args| -> Result<(), nvim_oxi::Error> {
-                    doc_set_text(args);
-                    Ok(())
+                TextDeltaEvent::Insert {
+                    text: "!".to_string(),
+                    attributes: None,
                 },
-            )),
-        ),
-        (
-            "doc_apply_edit",
-            Object::from(Function::<(String, usize, usize, String), ()>::from_fn(
-                |args| -> Result<(), nvim_oxi::Error> {
-                    doc_apply_edit(args);
-                    Ok(())
+            ]
+        );
+
+        // The earlier full-state deltas were left queued for polling
+        let queued = doc.poll_deltas(DEFAULT_CONTAINER);
+        assert!(
+            queued
+                .iter()
+                .any(|d| matches!(d, TextDeltaEvent::Insert { text, .. } if text == "Hello"))
+        );
+        assert!(
+            !queued
+                .iter()
+                .any(|d| matches!(d, TextDeltaEvent::Insert { text, .. } if text == "!"))
+        );
+    }
+
+    #[test]
+    fn test_validate_update() {
+        let mut source = CrdtDoc::new(Uuid::new_v4());
+        source.set_text(DEFAULT_CONTAINER, "Hello");
+        let update = source.encode_full_state_b64();
+
+        let doc_id = doc_create();
+        let result: serde_json::Value =
+            serde_json::from_str(&doc_validate_update((doc_id.clone(), update))).unwrap();
+        assert_eq!(result["valid"], true);
+        assert_eq!(result["new_size"], 5);
+        assert!(result["error"].is_null());
+        // The dry run left the real document untouched
+        assert_eq!(doc_get_text((doc_id.clone(), String::new())), "");
+        assert!(doc_poll_deltas((doc_id.clone(), String::new())).is_empty());
+
+        let result: serde_json::Value = serde_json::from_str(&doc_validate_update((
+            doc_id.clone(),
+            "not base64!".to_string(),
+        )))
+        .unwrap();
+        assert_eq!(result["valid"], false);
+        assert!(result["error"].as_str().unwrap().contains("base64"));
+
+        // Valid base64 that is not a Loro update fails the import
+        let garbage = base64::engine::general_purpose::STANDARD.encode(b"garbage");
+        let result: serde_json::Value =
+            serde_json::from_str(&doc_validate_update((doc_id.clone(), garbage))).unwrap();
+        assert_eq!(result["valid"], false);
+
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_apply_updates_bulk() {
+        let mut source = CrdtDoc::new(Uuid::new_v4());
+        let mut updates = Vec::new();
+        for word in ["one", " two", " three"] {
+            let vv = source.version_vector_b64();
+            source.apply_edit(DEFAULT_CONTAINER, usize::MAX, usize::MAX, word);
+            updates.push(source.encode_update_b64(&vv));
+        }
+        updates.push("not base64!".to_string());
+
+        let doc_id = doc_create();
+        let json = serde_json::to_string(&updates).unwrap();
+        assert_eq!(doc_apply_updates((doc_id.clone(), json)), 3);
+        assert_eq!(
+            doc_get_text((doc_id.clone(), String::new())),
+            "one two three"
+        );
+
+        assert_eq!(doc_apply_updates((doc_id.clone(), "{}".to_string())), 0);
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_out_of_order_import_is_pending() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello");
+        let first = doc_a.encode_full_state_b64();
+        let vv_after_first = doc_a.version_vector_b64();
+        doc_a.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+        let second = doc_a.encode_update_b64(&vv_after_first);
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        assert_eq!(doc_b.import_update_b64(&second), ImportOutcome::Pending);
+        assert_eq!(doc_b.pending_count(), 1);
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), "");
+
+        assert_eq!(doc_b.import_update_b64(&first), ImportOutcome::Applied);
+        assert_eq!(doc_b.pending_count(), 0);
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), "Hello World");
+    }
+
+    #[test]
+    fn test_missing_deps() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello");
+        let vv_after_first = doc_a.version_vector_b64();
+        doc_a.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+        let second = doc_a.encode_update_b64(&vv_after_first);
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        assert!(doc_b.missing_deps().is_none());
+
+        doc_b.import_update_b64(&second);
+        let missing = doc_b.missing_deps().expect("missing deps");
+        assert_eq!(missing.get(&doc_a.doc.peer_id()), Some(&5));
+
+        // Asking the peer for everything after our version fills the gap
+        let missing_b64 =
+            base64::engine::general_purpose::STANDARD.encode(doc_b.version_vector().encode());
+        let fill = doc_a.encode_update_b64(&missing_b64);
+        assert_eq!(doc_b.import_update_b64(&fill), ImportOutcome::Applied);
+        assert!(doc_b.missing_deps().is_none());
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), "Hello World");
+    }
+
+    #[test]
+    fn test_import_invalid_update_fails() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        assert_eq!(
+            doc.import_update_b64("not base64!!!"),
+            ImportOutcome::Failed
+        );
+        assert!(!doc.apply_update_b64("not base64!!!"));
+    }
+
+    #[test]
+    fn test_drain_and_get_text() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        assert_eq!(
+            doc_b.drain_and_get_text(DEFAULT_CONTAINER),
+            (false, String::new())
+        );
+
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        assert_eq!(
+            doc_b.drain_and_get_text(DEFAULT_CONTAINER),
+            (true, "Hello".to_string())
+        );
+        assert!(doc_b.poll_deltas(DEFAULT_CONTAINER).is_empty());
+        assert_eq!(
+            doc_b.drain_and_get_text(DEFAULT_CONTAINER),
+            (false, "Hello".to_string())
+        );
+    }
+
+    #[test]
+    fn test_deltas_routed_per_container() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text("main.rs", "fn main() {}");
+        doc_a.set_text("README.md", "# Tandem");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        assert_eq!(doc_b.get_text("main.rs"), "fn main() {}");
+        assert_eq!(doc_b.get_text("README.md"), "# Tandem");
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), "");
+        doc_b.clear_pending_deltas();
+
+        let vv_b = doc_b.version_vector_b64();
+        doc_a.apply_edit("README.md", 8, 8, "!");
+        doc_b.apply_update_b64(&doc_a.encode_update_b64(&vv_b));
+
+        assert!(doc_b.poll_deltas("main.rs").is_empty());
+        assert!(doc_b.poll_deltas(DEFAULT_CONTAINER).is_empty());
+        let deltas = doc_b.poll_deltas("README.md");
+        assert!(
+            deltas
+                .iter()
+                .any(|d| matches!(d, TextDeltaEvent::Insert { text, .. } if text == "!"))
+        );
+        assert_eq!(doc_b.get_text("README.md"), "# Tandem!");
+    }
+
+    #[test]
+    fn test_container_name_defaults_to_content() {
+        assert_eq!(container_name(""), DEFAULT_CONTAINER);
+        assert_eq!(container_name("notes"), "notes");
+    }
+
+    #[test]
+    fn test_textdelta_event_attributes_serialization() {
+        let bold = Attributes::from([("bold".to_string(), LoroValue::from(true))]);
+        let retain = TextDeltaEvent::Retain {
+            len: 5,
+            attributes: Some(bold),
+        };
+        assert_eq!(
+            retain.to_json(),
+            r#"{"type":"retain","len":5,"attributes":{"bold":true}}"#
+        );
+    }
+
+    #[test]
+    fn test_mark_roundtrip() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello World");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        doc_b.clear_pending_deltas();
+
+        let vv_b = doc_b.version_vector_b64();
+        assert!(doc_a.mark(0, 5, "bold", LoroValue::from(true)));
+        doc_b.apply_update_b64(&doc_a.encode_update_b64(&vv_b));
+
+        let deltas = doc_b.poll_deltas(DEFAULT_CONTAINER);
+        let bold = deltas.iter().find_map(|d| match d {
+            TextDeltaEvent::Retain {
+                len,
+                attributes: Some(attrs),
+            } => Some((*len, attrs.get("bold").cloned())),
+            _ => None,
+        });
+        assert_eq!(bold, Some((5, Some(LoroValue::from(true)))));
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), "Hello World");
+
+        let vv_b = doc_b.version_vector_b64();
+        assert!(doc_a.unmark(0, 5, "bold"));
+        doc_b.apply_update_b64(&doc_a.encode_update_b64(&vv_b));
+        let deltas = doc_b.poll_deltas(DEFAULT_CONTAINER);
+        assert!(deltas.iter().any(|d| matches!(
+            d,
+            TextDeltaEvent::Retain { attributes: Some(attrs), .. }
+                if attrs.get("bold") == Some(&LoroValue::Null)
+        )));
+    }
+
+    #[test]
+    fn test_revert_to() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        let acked = doc.version_vector_b64();
+
+        doc.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+        assert!(doc.revert_to_b64(&acked));
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello");
+
+        // The revert is an ordinary change that peers receive as an update
+        let mut peer = CrdtDoc::new(Uuid::new_v4());
+        peer.apply_update_b64(&doc.encode_full_state_b64());
+        assert_eq!(peer.get_text(DEFAULT_CONTAINER), "Hello");
+    }
+
+    #[test]
+    fn test_revert_to_unknown_version_fails() {
+        let mut other = CrdtDoc::new(Uuid::new_v4());
+        other.set_text(DEFAULT_CONTAINER, "elsewhere");
+
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        assert!(!doc.revert_to_b64(&other.version_vector_b64()));
+        assert!(!doc.revert_to_b64("not base64!"));
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello");
+    }
+
+    #[test]
+    fn test_sync_status() {
+        let doc_id = doc_create();
+        doc_set_text((doc_id.clone(), String::new(), "Hello".to_string()));
+        let synced = doc_state_vector(doc_id.clone());
+        assert!(!doc_is_ahead((doc_id.clone(), synced.clone())));
+        assert_eq!(doc_missing_from((doc_id.clone(), synced.clone())), 0);
+
+        // Two inserted characters are two ops the remote hasn't seen
+        doc_apply_edit((doc_id.clone(), String::new(), 5, 5, "!".to_string()));
+        doc_apply_edit((doc_id.clone(), String::new(), 6, 6, "?".to_string()));
+        assert!(doc_is_ahead((doc_id.clone(), synced.clone())));
+        assert_eq!(doc_missing_from((doc_id.clone(), synced)), 2);
+
+        // An empty remote is missing everything
+        let empty = base64::engine::general_purpose::STANDARD.encode(VersionVector::new().encode());
+        assert_eq!(doc_missing_from((doc_id.clone(), empty)), 7);
+        assert_eq!(
+            doc_missing_from((doc_id.clone(), "not base64!".to_string())),
+            -1
+        );
+
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_common_ancestor() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        let base = doc_b.version_vector();
+
+        // Diverge: both sides edit independently
+        doc_a.apply_edit(DEFAULT_CONTAINER, 5, 5, " A");
+        doc_b.apply_edit(DEFAULT_CONTAINER, 5, 5, " B");
+
+        assert_eq!(doc_a.common_ancestor(&doc_b.version_vector()), base);
+        assert_eq!(doc_b.common_ancestor(&doc_a.version_vector()), base);
+
+        // The ancestor is a version we can check out to diff each side against
+        let frontiers = doc_a.doc.vv_to_frontiers(&base);
+        let fork = doc_a.doc.fork_at(&frontiers).unwrap();
+        assert_eq!(fork.get_text(DEFAULT_CONTAINER).to_string(), "Hello");
+    }
+
+    #[test]
+    fn test_undo_redo() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        assert!(!doc.undo.can_undo());
+
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        doc.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+        assert!(doc.undo.can_undo());
+
+        assert!(doc.undo());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello");
+        assert!(doc.undo.can_redo());
+
+        assert!(doc.redo());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello World");
+        assert!(!doc.redo());
+    }
+
+    #[test]
+    fn test_undo_ignores_remote_edits() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        assert!(!doc_b.undo.can_undo());
+
+        // Local edit on B, then a remote edit from A
+        doc_b.apply_edit(DEFAULT_CONTAINER, 5, 5, "!");
+        let vv_b = doc_b.version_vector_b64();
+        let vv_a = doc_a.version_vector_b64();
+        doc_a.apply_edit(DEFAULT_CONTAINER, 0, 0, ">> ");
+        doc_b.apply_update_b64(&doc_a.encode_update_b64(&vv_b));
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), ">> Hello!");
+
+        // Undo only reverts B's own edit and the result reaches A
+        assert!(doc_b.undo());
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), ">> Hello");
+        assert!(!doc_b.undo());
+
+        doc_a.apply_update_b64(&doc_b.encode_update_b64(&vv_a));
+        assert_eq!(doc_a.get_text(DEFAULT_CONTAINER), ">> Hello");
+    }
+
+    #[test]
+    fn test_cursor_follows_remote_insert() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "Hello World");
+        let cursor = doc_a.cursor_create(5).unwrap();
+        assert_eq!(doc_a.cursor_resolve(&cursor), Some(5));
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        let vv_a = doc_a.version_vector_b64();
+        doc_b.apply_edit(DEFAULT_CONTAINER, 0, 0, ">> ");
+        doc_a.apply_update_b64(&doc_b.encode_update_b64(&vv_a));
+
+        assert_eq!(doc_a.cursor_resolve(&cursor), Some(8));
+        // The handle is portable: B resolves it against its own state
+        assert_eq!(doc_b.cursor_resolve(&cursor), Some(8));
+    }
+
+    #[test]
+    fn test_cursor_invalid_input() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hi");
+        assert!(doc.cursor_create(100).is_none());
+        assert!(doc.cursor_resolve(b"garbage").is_none());
+    }
+
+    /// Serializes tests that depend on the global update size limit
+    static UPDATE_SIZE_LIMIT: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn test_oversized_update_rejected() {
+        let _limit = UPDATE_SIZE_LIMIT.lock();
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        let oversized = "A".repeat(DEFAULT_MAX_UPDATE_B64_LEN + 4);
+        assert_eq!(doc.import_update_b64(&oversized), ImportOutcome::TooLarge);
+        assert!(check_update_size(DEFAULT_MAX_UPDATE_B64_LEN).is_ok());
+    }
+
+    #[test]
+    fn test_delta_queue_overflow() {
+        let mut source = CrdtDoc::new(Uuid::new_v4());
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        // Fill the queue to the cap so the next import overflows it
+        doc.pending_deltas.lock().insert(
+            DEFAULT_CONTAINER.to_string(),
+            vec![TextDeltaEvent::Delete { len: 1 }; DEFAULT_MAX_PENDING_DELTAS],
+        );
+        assert!(!doc.take_overflow(DEFAULT_CONTAINER));
+
+        source.set_text(DEFAULT_CONTAINER, "x");
+        doc.apply_update_b64(&source.encode_full_state_b64());
+        assert_eq!(
+            doc.pending_deltas.lock()[DEFAULT_CONTAINER].len(),
+            DEFAULT_MAX_PENDING_DELTAS
+        );
+
+        // The flag is reported once and the stale deltas are dropped with it
+        assert!(doc.take_overflow(DEFAULT_CONTAINER));
+        assert!(!doc.take_overflow(DEFAULT_CONTAINER));
+        assert!(doc.poll_deltas(DEFAULT_CONTAINER).is_empty());
+    }
+
+    #[test]
+    fn test_list_containers() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        assert!(doc.list_containers().is_empty());
+
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        doc.set_text("notes", "todo");
+        assert!(doc.has_container("notes"));
+        assert!(!doc.has_container("missing"));
+        assert_eq!(
+            doc.list_containers(),
+            vec![
+                ContainerInfo {
+                    name: "content".to_string(),
+                    kind: "Text".to_string(),
                 },
-            )),
-        ),
-        (
-            "doc_state_vector",
-            Object::from(Function::<String, String>::from_fn(
-                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_state_vector(id)) },
-            )),
-        ),
-        (
-            "doc_apply_update",
-            Object::from(Function::<(String, String), bool>::from_fn(
-                |args| -> Result<bool, nvim_oxi::Error> { Ok(doc_apply_update(args)) },
-            )),
-        ),
-        (
-            "doc_encode_update",
-            Object::from(Function::<(String, String), String>::from_fn(
-                |args| -> Result<String, nvim_oxi::Error> { Ok(doc_encode_update(args)) },
-            )),
-        ),
-        (
-            "doc_encode_full_state",
-            Object::from(Function::<String, String>::from_fn(
-                |id| -> Result<String, nvim_oxi::Error> { Ok(doc_encode_full_state(id)) },
-            )),
-        ),
-        (
-            "doc_poll_deltas",
-            Object::from(Function::<String, Vec<String>>::from_fn(
-                |id| -> Result<Vec<String>, nvim_oxi::Error> { Ok(doc_poll_deltas(id)) },
-            )),
-        ),
-        (
-            "doc_clear_deltas",
-            Object::from(Function::<String, ()>::from_fn(
-                |id| -> Result<(), nvim_oxi::Error> {
-                    doc_clear_deltas(id);
-                    Ok(())
+                ContainerInfo {
+                    name: "notes".to_string(),
+                    kind: "Text".to_string(),
                 },
-            )),
-        ),
-    ])
-}
+            ]
+        );
+    }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_json_roundtrip() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        doc.set_text("notes", "todo");
+
+        let json = doc.export_json();
+        let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["content"], "Hello");
+        assert_eq!(value["notes"], "todo");
+
+        let mut fresh = CrdtDoc::new(Uuid::new_v4());
+        assert!(fresh.import_json(&json));
+        assert_eq!(fresh.get_text(DEFAULT_CONTAINER), "Hello");
+        assert_eq!(fresh.get_text("notes"), "todo");
+
+        assert!(!fresh.import_json("[1, 2]"));
+    }
 
     #[test]
-    fn test_loro_sync_roundtrip() {
-        // Create doc A with content
-        let doc_a = LoroDoc::new();
-        let text_a = doc_a.get_text("content");
-        text_a.insert_utf8(0, "Hello World").unwrap();
+    fn test_save_and_load() {
+        let path = std::env::temp_dir().join(format!("tandem-test-{}.loro", Uuid::new_v4()));
 
-        assert_eq!(text_a.to_string(), "Hello World");
+        let doc_id = doc_create();
+        doc_set_text((doc_id.clone(), String::new(), "Saved content".to_string()));
+        assert!(doc_save((doc_id.clone(), path.display().to_string())));
 
-        // Export all updates from A
-        let updates = doc_a
-            .export(ExportMode::all_updates())
-            .expect("export failed");
-        let updates_b64 = base64::engine::general_purpose::STANDARD.encode(&updates);
+        let loaded_id = doc_load(path.display().to_string());
+        assert!(!loaded_id.is_empty());
+        assert_ne!(loaded_id, doc_id);
+        assert_eq!(
+            doc_get_text((loaded_id.clone(), String::new())),
+            "Saved content"
+        );
+        assert!(doc_poll_deltas((loaded_id.clone(), String::new())).is_empty());
 
-        println!(
-            "Export size: {} bytes, b64 len: {}",
-            updates.len(),
-            updates_b64.len()
+        // The loaded doc keeps receiving remote updates through its subscription
+        let mut remote = CrdtDoc::new(Uuid::new_v4());
+        let loaded_vv = doc_state_vector(loaded_id.clone());
+        let full = doc_encode_full_state(loaded_id.clone());
+        remote.apply_update_b64(&full);
+        remote.apply_edit(DEFAULT_CONTAINER, 0, 0, "> ");
+        assert!(doc_apply_update((
+            loaded_id.clone(),
+            remote.encode_update_b64(&loaded_vv)
+        )));
+        assert!(!doc_poll_deltas((loaded_id.clone(), String::new())).is_empty());
+
+        doc_destroy(doc_id);
+        doc_destroy(loaded_id);
+        let _ = fs::remove_file(&path);
+
+        assert!(doc_load(path.display().to_string()).is_empty());
+    }
+
+    #[test]
+    fn test_get_history() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        for (i, word) in ["one", "two", "three"].iter().enumerate() {
+            // Distinct messages keep Loro from merging the commits into one change
+            doc.doc.set_next_commit_message(&format!("commit {i}"));
+            doc.apply_edit(DEFAULT_CONTAINER, usize::MAX, usize::MAX, word);
+        }
+
+        let history = doc.history(0, 0);
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0].message.as_deref(), Some("commit 0"));
+        assert_eq!(history[0].counter_start, 0);
+        assert_eq!(history[0].counter_end, 3);
+        assert_eq!(history[2].message.as_deref(), Some("commit 2"));
+        assert_eq!(history[2].peer, doc.doc.peer_id().to_string());
+
+        let page = doc.history(1, 1);
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].message.as_deref(), Some("commit 1"));
+        assert!(doc.history(3, 10).is_empty());
+    }
+
+    #[test]
+    fn test_detached_branch_merge() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        let base_vv = doc.version_vector_b64();
+
+        assert!(doc.start_branch());
+        assert!(!doc.start_branch());
+        doc.apply_edit(DEFAULT_CONTAINER, 5, 5, " suggestion");
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello suggestion");
+
+        // Branch edits are not visible to peers until merged
+        assert_eq!(doc.version_vector_b64(), base_vv);
+
+        // Remote edits land on the main line but are not polled into the branch view
+        let mut remote = CrdtDoc::new(Uuid::new_v4());
+        remote.apply_update_b64(&doc.encode_full_state_b64());
+        remote.apply_edit(DEFAULT_CONTAINER, 0, 0, "> ");
+        doc.apply_update_b64(&remote.encode_update_b64(&base_vv));
+        assert!(doc.poll_deltas(DEFAULT_CONTAINER).is_empty());
+
+        assert!(doc.merge_branch());
+        assert!(doc.branch.is_none());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "> Hello suggestion");
+        assert!(doc.poll_deltas(DEFAULT_CONTAINER).is_empty());
+
+        remote.apply_update_b64(&doc.encode_update_b64(&remote.version_vector_b64()));
+        assert_eq!(remote.get_text(DEFAULT_CONTAINER), "> Hello suggestion");
+    }
+
+    #[test]
+    fn test_detached_branch_discard() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        let base_vv = doc.version_vector_b64();
+
+        assert!(!doc.discard_branch());
+        assert!(doc.start_branch());
+        doc.apply_edit(DEFAULT_CONTAINER, 0, 5, "Bye");
+        assert!(doc.discard_branch());
+
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello");
+        assert_eq!(doc.version_vector_b64(), base_vv);
+        assert!(!doc.merge_branch());
+    }
+
+    #[test]
+    fn test_branch_blocks_main_line_operations() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        let base_vv = doc.version_vector_b64();
+        let cursor = doc.cursor_create(2).expect("cursor");
+
+        assert!(doc.start_branch());
+        doc.apply_edit(DEFAULT_CONTAINER, 5, 5, " suggestion");
+        assert!(!doc.undo());
+        assert!(!doc.redo());
+        assert!(doc.cursor_create(0).is_none());
+        assert!(doc.cursor_resolve(&cursor).is_none());
+        assert!(!doc.revert_to_b64(&base_vv));
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello suggestion");
+        assert_eq!(doc.version_vector_b64(), base_vv);
+
+        // Once the branch is closed, the main line is usable again
+        assert!(doc.discard_branch());
+        assert_eq!(doc.cursor_resolve(&cursor), Some(2));
+        assert!(doc.undo());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "");
+    }
+
+    #[test]
+    fn test_destroy_all() {
+        // A private registry, so other tests' documents in DOCS are left alone
+        let registry = Mutex::new(HashMap::new());
+        for _ in 0..2 {
+            let id = Uuid::new_v4();
+            registry.lock().insert(id, CrdtDoc::new(id));
+        }
+
+        assert_eq!(destroy_all_in(&registry), 2);
+        assert!(registry.lock().is_empty());
+        assert_eq!(destroy_all_in(&registry), 0);
+    }
+
+    #[test]
+    fn test_doc_list() {
+        let a = doc_create();
+        let b = doc_create();
+
+        let ids: Vec<String> = serde_json::from_str(&doc_list()).unwrap();
+        assert!(ids.contains(&a));
+        assert!(ids.contains(&b));
+
+        doc_destroy(a.clone());
+        let ids: Vec<String> = serde_json::from_str(&doc_list()).unwrap();
+        assert!(!ids.contains(&a));
+        assert!(ids.contains(&b));
+        doc_destroy(b);
+    }
+
+    #[test]
+    fn test_doc_diff() {
+        let a_id = doc_create();
+        let b_id = doc_create();
+        doc_set_text((a_id.clone(), String::new(), "Hello".to_string()));
+        let full = doc_encode_full_state(a_id.clone());
+        doc_apply_update((b_id.clone(), full));
+        doc_apply_edit((b_id.clone(), String::new(), 5, 5, " World".to_string()));
+
+        let diff = doc_diff((a_id.clone(), b_id.clone()));
+        assert!(!diff.is_empty());
+        doc_apply_update((a_id.clone(), diff));
+        assert_eq!(doc_get_text((a_id.clone(), String::new())), "Hello World");
+        assert_eq!(doc_get_text((b_id.clone(), String::new())), "Hello World");
+
+        // Both docs must exist
+        assert!(doc_diff((a_id.clone(), Uuid::new_v4().to_string())).is_empty());
+        assert!(doc_diff(("not-a-uuid".to_string(), b_id.clone())).is_empty());
+
+        doc_destroy(a_id);
+        doc_destroy(b_id);
+    }
+
+    #[test]
+    fn test_doc_merge() {
+        let target_id = doc_create();
+        let source_id = doc_create();
+        doc_set_text((target_id.clone(), String::new(), "Hello".to_string()));
+        doc_apply_update((source_id.clone(), doc_encode_full_state(target_id.clone())));
+        doc_apply_edit((source_id.clone(), String::new(), 5, 5, " World".to_string()));
+        doc_clear_deltas(target_id.clone());
+
+        assert!(doc_merge((target_id.clone(), source_id.clone())));
+        assert_eq!(
+            doc_get_text((target_id.clone(), String::new())),
+            "Hello World"
         );
+        assert!(!doc_poll_deltas((target_id.clone(), String::new())).is_empty());
 
-        // Create doc B and import
-        let doc_b = LoroDoc::new();
-        let updates_decoded = base64::engine::general_purpose::STANDARD
-            .decode(&updates_b64)
-            .expect("decode failed");
-        doc_b.import(&updates_decoded).expect("import failed");
+        // Nothing left to merge, and a doc can't be merged into itself
+        assert!(!doc_merge((target_id.clone(), source_id.clone())));
+        assert!(!doc_merge((target_id.clone(), target_id.clone())));
+        assert!(!doc_merge((target_id.clone(), Uuid::new_v4().to_string())));
 
-        let text_b = doc_b.get_text("content");
-        assert_eq!(text_b.to_string(), "Hello World");
+        doc_destroy(target_id);
+        doc_destroy(source_id);
     }
 
     #[test]
-    fn test_textdelta_subscription() {
-        use std::sync::atomic::{AtomicUsize, Ordering};
+    fn test_doc_merge_ignores_update_size_limit() {
+        let _limit = UPDATE_SIZE_LIMIT.lock();
+        let target_id = doc_create();
+        let source_id = doc_create();
+        // Random text, so the export can't compress below the limit
+        let text: String = rand::Rng::sample_iter(rand::rng(), rand::distr::Alphanumeric)
+            .take(256 * 1024)
+            .map(char::from)
+            .collect();
+        doc_set_text((source_id.clone(), String::new(), text.clone()));
 
-        // Create doc A with content
-        let doc_a = LoroDoc::new();
-        let text_a = doc_a.get_text("content");
-        text_a.insert_utf8(0, "Hello").unwrap();
-        doc_a.commit();
+        // Well above every other test's updates, but below this export
+        crdt_set_max_update_size(64 * 1024);
+        let export = doc_encode_full_state(source_id.clone());
+        doc_apply_update((target_id.clone(), export));
+        let rejected = doc_get_text((target_id.clone(), String::new())).is_empty();
+        let merged = doc_merge((target_id.clone(), source_id.clone()));
+        crdt_set_max_update_size(DEFAULT_MAX_UPDATE_B64_LEN);
 
-        // Export from A
-        let updates_a = doc_a
-            .export(ExportMode::all_updates())
-            .expect("export failed");
+        assert!(rejected);
+        assert!(merged);
+        assert_eq!(doc_get_text((target_id.clone(), String::new())), text);
 
-        // Create doc B with subscription
-        let doc_b = LoroDoc::new();
-        let delta_count = Arc::new(AtomicUsize::new(0));
-        let delta_count_clone = Arc::clone(&delta_count);
+        doc_destroy(target_id);
+        doc_destroy(source_id);
+    }
 
-        let _sub = doc_b.subscribe_root(Arc::new(move |event| {
-            if matches!(event.triggered_by, EventTriggerKind::Import) {
-                for diff in &event.events {
-                    if let Diff::Text(deltas) = &diff.diff {
-                        delta_count_clone.fetch_add(deltas.len(), Ordering::SeqCst);
-                    }
-                }
-            }
-        }));
+    #[test]
+    fn test_export_from_frontiers() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
 
-        // Import into B - should trigger subscription
-        doc_b.import(&updates_a).expect("import failed");
+        let mut peer = CrdtDoc::new(Uuid::new_v4());
+        assert!(peer.apply_update_b64(&doc.encode_full_state_b64()));
+        let frontiers = doc.frontiers_b64();
+        assert!(!frontiers.is_empty());
 
-        // Verify we got delta events
+        doc.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+        let update = doc.export_from_frontiers_b64(&frontiers);
+        assert!(!update.is_empty());
+        assert_eq!(peer.import_update_b64(&update), ImportOutcome::Applied);
+        assert_eq!(peer.get_text(DEFAULT_CONTAINER), "Hello World");
+        assert_eq!(peer.frontiers_b64(), doc.frontiers_b64());
+
+        // Frontiers unknown to this doc and malformed input export nothing
+        let other = CrdtDoc::new(Uuid::new_v4());
+        assert!(other.export_from_frontiers_b64(&frontiers).is_empty());
+        assert!(doc.export_from_frontiers_b64("not base64!").is_empty());
+    }
+
+    #[test]
+    fn test_checkout_past_version() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        let past = doc.frontiers_b64();
+        doc.apply_edit(DEFAULT_CONTAINER, 5, 5, " World");
+
+        assert!(!doc.checkout_to_latest());
+        assert!(doc.checkout_b64(&past));
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello");
+        assert!(doc.poll_deltas(DEFAULT_CONTAINER).is_empty());
+        let deltas = doc.poll_checkout_deltas(DEFAULT_CONTAINER);
         assert!(
-            delta_count.load(Ordering::SeqCst) > 0,
-            "Should have received delta events"
+            deltas
+                .iter()
+                .any(|d| matches!(d, TextDeltaEvent::Delete { len: 6 }))
         );
 
-        let text_b = doc_b.get_text("content");
-        assert_eq!(text_b.to_string(), "Hello");
+        assert!(doc.checkout_to_latest());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello World");
+        let deltas = doc.poll_checkout_deltas(DEFAULT_CONTAINER);
+        assert!(
+            deltas
+                .iter()
+                .any(|d| matches!(d, TextDeltaEvent::Insert { text, .. } if text == " World"))
+        );
+
+        assert!(!doc.checkout_b64("not base64!"));
     }
 
     #[test]
-    fn test_textdelta_event_serialization() {
-        let retain = TextDeltaEvent::Retain { len: 5 };
-        assert_eq!(retain.to_json(), r#"{"type":"retain","len":5}"#);
+    fn test_fork_is_independent() {
+        let doc_id = doc_create();
+        doc_set_text((doc_id.clone(), String::new(), "Hello".to_string()));
 
-        let insert = TextDeltaEvent::Insert {
-            text: "hello".to_string(),
-        };
-        assert_eq!(insert.to_json(), r#"{"type":"insert","text":"hello"}"#);
+        let fork_id = doc_fork(doc_id.clone());
+        assert!(!fork_id.is_empty());
+        assert_ne!(fork_id, doc_id);
+        assert_eq!(doc_get_text((fork_id.clone(), String::new())), "Hello");
+        assert!(doc_poll_deltas((fork_id.clone(), String::new())).is_empty());
 
-        let delete = TextDeltaEvent::Delete { len: 3 };
-        assert_eq!(delete.to_json(), r#"{"type":"delete","len":3}"#);
+        doc_apply_edit((fork_id.clone(), String::new(), 5, 5, " scratch".to_string()));
+        assert_eq!(
+            doc_get_text((fork_id.clone(), String::new())),
+            "Hello scratch"
+        );
+        assert_eq!(doc_get_text((doc_id.clone(), String::new())), "Hello");
+        assert!(doc_poll_deltas((doc_id.clone(), String::new())).is_empty());
+
+        assert!(doc_fork(Uuid::new_v4().to_string()).is_empty());
+
+        doc_destroy(fork_id);
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_echo_local_edits() {
+        let doc_id = doc_create();
+        doc_set_text((doc_id.clone(), String::new(), "Hello".to_string()));
+        assert!(doc_poll_deltas((doc_id.clone(), String::new())).is_empty());
+
+        assert!(doc_set_echo_local((doc_id.clone(), true)));
+        doc_apply_edit((doc_id.clone(), String::new(), 5, 5, "!".to_string()));
+        let deltas = doc_poll_deltas((doc_id.clone(), String::new()));
+        assert_eq!(deltas.len(), 2);
+        assert!(deltas[1].contains(r#""text":"!""#), "{:?}", deltas);
+        assert!(doc_poll_deltas((doc_id.clone(), String::new())).is_empty());
+
+        // Line mode sees the echoed edit relative to the text before it
+        doc_apply_edit((doc_id.clone(), String::new(), 0, 1, "J".to_string()));
+        let edits = doc_poll_deltas_lines(doc_id.clone());
+        assert_eq!(edits.len(), 2, "{:?}", edits);
+
+        assert!(doc_set_echo_local((doc_id.clone(), false)));
+        doc_apply_edit((doc_id.clone(), String::new(), 0, 0, ">".to_string()));
+        assert!(doc_poll_deltas((doc_id.clone(), String::new())).is_empty());
+        assert!(!doc_set_echo_local((Uuid::new_v4().to_string(), true)));
+
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_create_with_peer() {
+        let peer: u64 = 4_242_424_242;
+        let doc_id = doc_create_with_peer(peer.to_string());
+        assert!(!doc_id.is_empty());
+
+        // Duplicate and malformed peer IDs are rejected
+        assert!(doc_create_with_peer(peer.to_string()).is_empty());
+        assert!(doc_create_with_peer("not-a-number".to_string()).is_empty());
+        assert!(doc_create_with_peer("-1".to_string()).is_empty());
+
+        doc_set_text((doc_id.clone(), String::new(), "Hello".to_string()));
+        let mut other = CrdtDoc::new(Uuid::new_v4());
+        other.apply_update_b64(&doc_encode_full_state(doc_id.clone()));
+        assert_eq!(other.version_vector().get(&peer), Some(&5));
+
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_batch_edits_single_change() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+
+        // A distinct message stops Loro from merging the batch into the previous change
+        doc.doc.set_next_commit_message("batch");
+        assert!(doc.begin_batch());
+        assert!(!doc.begin_batch());
+        for c in ["a", "b", "c", "d", "e"] {
+            doc.apply_edit(DEFAULT_CONTAINER, usize::MAX, usize::MAX, c);
+        }
+        assert!(doc.commit_batch());
+        assert!(!doc.commit_batch());
+
+        let mut peer = CrdtDoc::new(Uuid::new_v4());
+        peer.apply_update_b64(&doc.encode_full_state_b64());
+        assert_eq!(peer.get_text(DEFAULT_CONTAINER), "Helloabcde");
+
+        let history = peer.history(0, 0);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].message.as_deref(), Some("batch"));
+        assert_eq!(history[1].counter_end - history[1].counter_start, 5);
+    }
+
+    #[test]
+    fn test_batch_includes_set_text_and_marks() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+
+        doc.doc.set_next_commit_message("batch");
+        assert!(doc.begin_batch());
+        doc.set_text(DEFAULT_CONTAINER, "Hello World");
+        assert!(doc.mark(0, 5, "bold", LoroValue::from(true)));
+        doc.apply_edit(DEFAULT_CONTAINER, usize::MAX, usize::MAX, "!");
+        assert!(doc.unmark(0, 2, "bold"));
+        assert!(doc.commit_batch());
+
+        let mut peer = CrdtDoc::new(Uuid::new_v4());
+        peer.apply_update_b64(&doc.encode_full_state_b64());
+        assert_eq!(peer.get_text(DEFAULT_CONTAINER), "Hello World!");
+
+        let history = peer.history(0, 0);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].message.as_deref(), Some("batch"));
+    }
+
+    #[test]
+    fn test_batch_blocks_undo_and_revert() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        let base_vv = doc.version_vector_b64();
+
+        doc.doc.set_next_commit_message("batch");
+        assert!(doc.begin_batch());
+        doc.apply_edit(DEFAULT_CONTAINER, usize::MAX, usize::MAX, " World");
+        assert!(!doc.undo());
+        assert!(!doc.redo());
+        assert!(!doc.revert_to_b64(&base_vv));
+        assert!(doc.commit_batch());
+
+        let mut peer = CrdtDoc::new(Uuid::new_v4());
+        peer.apply_update_b64(&doc.encode_full_state_b64());
+        assert_eq!(peer.get_text(DEFAULT_CONTAINER), "Hello World");
+        let history = peer.history(0, 0);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[1].message.as_deref(), Some("batch"));
+
+        // Once the batch is committed, undo works again
+        assert!(doc.undo());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), "Hello");
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, &"x".repeat(1000));
+
+        let stats = doc.stats();
+        assert_eq!(stats.text_bytes, 1000);
+        assert_eq!(stats.text_chars, 1000);
+        assert!(stats.snapshot_bytes > 0);
+        assert!(stats.updates_bytes > 0);
+
+        doc.set_text(DEFAULT_CONTAINER, "héllo");
+        let stats = doc.stats();
+        assert_eq!(stats.text_bytes, 6);
+        assert_eq!(stats.text_chars, 5);
+    }
+
+    #[test]
+    fn test_compact() {
+        let mut doc = CrdtDoc::new(Uuid::new_v4());
+        doc.set_text(DEFAULT_CONTAINER, "Hello");
+        for i in 0..100 {
+            doc.apply_edit(DEFAULT_CONTAINER, 0, 1, &format!("{}", i % 10));
+        }
+        let text = doc.get_text(DEFAULT_CONTAINER);
+        let peer = doc.doc.peer_id();
+        let before = doc.stats().updates_bytes;
+
+        assert!(doc.compact());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), text);
+        assert!(doc.stats().updates_bytes < before);
+        assert_ne!(doc.doc.peer_id(), peer);
+        assert!(doc.poll_deltas(DEFAULT_CONTAINER).is_empty());
+
+        // The compacted doc keeps editing and syncing
+        let mut peer_doc = CrdtDoc::new(Uuid::new_v4());
+        peer_doc.apply_update_b64(&doc.encode_full_state_b64());
+        assert_eq!(peer_doc.get_text(DEFAULT_CONTAINER), text);
+
+        let vv = doc.version_vector_b64();
+        peer_doc.apply_edit(DEFAULT_CONTAINER, 0, 0, ">");
+        doc.apply_update_b64(&peer_doc.encode_update_b64(&vv));
+        assert!(!doc.poll_deltas(DEFAULT_CONTAINER).is_empty());
+        assert_eq!(doc.get_text(DEFAULT_CONTAINER), format!(">{text}"));
+    }
+
+    #[test]
+    fn test_poll_line_edits() {
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, "xy");
+
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        doc_b.clear_pending_deltas();
+
+        let vv_b = doc_b.version_vector_b64();
+        doc_a.apply_edit(DEFAULT_CONTAINER, 1, 1, "a\nb");
+        doc_b.apply_update_b64(&doc_a.encode_update_b64(&vv_b));
 
-        // Test with special characters
-        let insert_special = TextDeltaEvent::Insert {
-            text: "hello\nworld".to_string(),
-        };
         assert_eq!(
-            insert_special.to_json(),
-            r#"{"type":"insert","text":"hello\nworld"}"#
+            doc_b.poll_line_edits(),
+            vec![LineEdit {
+                start_row: 0,
+                start_col: 1,
+                end_row: 0,
+                end_col: 1,
+                text: "a\nb".to_string(),
+            }]
+        );
+        assert_eq!(doc_b.get_text(DEFAULT_CONTAINER), "xa\nby");
+        assert!(doc_b.poll_line_edits().is_empty());
+    }
+
+    #[test]
+    fn test_utf16_conversion() {
+        // "é" is 2 bytes / 1 unit, "😀" is 4 bytes / 2 units (a surrogate pair)
+        let text = "aé😀b";
+        let pairs = [(0, 0), (1, 1), (3, 2), (7, 4), (8, 5)];
+        for (byte, utf16) in pairs {
+            assert_eq!(byte_to_utf16(text, byte), Some(utf16));
+            assert_eq!(utf16_to_byte(text, utf16), Some(byte));
+        }
+
+        // Offsets inside a character or past the end are rejected
+        assert_eq!(utf16_to_byte(text, 3), None);
+        assert_eq!(utf16_to_byte(text, 6), None);
+        assert_eq!(byte_to_utf16(text, 2), None);
+        assert_eq!(byte_to_utf16(text, 5), None);
+        assert_eq!(byte_to_utf16(text, 9), None);
+
+        let doc_id = doc_create();
+        doc_set_text((doc_id.clone(), String::new(), text.to_string()));
+        assert_eq!(doc_utf16_to_byte((doc_id.clone(), 4)), 7);
+        assert_eq!(doc_byte_to_utf16((doc_id.clone(), 7)), 4);
+        assert_eq!(doc_utf16_to_byte((doc_id.clone(), 3)), -1);
+        doc_destroy(doc_id);
+    }
+
+    #[test]
+    fn test_line_edits_multibyte() {
+        // Delete "ö" from the second line, then insert after the multi-byte "é"
+        let base = "héllo\nwörld";
+        let deltas = vec![
+            TextDeltaEvent::Retain {
+                len: 2,
+                attributes: None,
+            },
+            TextDeltaEvent::Insert {
+                text: "!".to_string(),
+                attributes: None,
+            },
+            TextDeltaEvent::Retain {
+                len: 5,
+                attributes: None,
+            },
+            TextDeltaEvent::Delete { len: 1 },
+        ];
+
+        let edits = deltas_to_line_edits(base, &deltas);
+        assert_eq!(
+            edits,
+            vec![
+                LineEdit {
+                    start_row: 0,
+                    start_col: 3,
+                    end_row: 0,
+                    end_col: 3,
+                    text: "!".to_string(),
+                },
+                LineEdit {
+                    start_row: 1,
+                    start_col: 1,
+                    end_row: 1,
+                    end_col: 3,
+                    text: String::new(),
+                },
+            ]
+        );
+
+        // Same deletion arriving through a real import
+        let mut doc_a = CrdtDoc::new(Uuid::new_v4());
+        doc_a.set_text(DEFAULT_CONTAINER, base);
+        let mut doc_b = CrdtDoc::new(Uuid::new_v4());
+        doc_b.apply_update_b64(&doc_a.encode_full_state_b64());
+        doc_b.clear_pending_deltas();
+
+        let vv_b = doc_b.version_vector_b64();
+        doc_a.apply_edit(DEFAULT_CONTAINER, 8, 10, "");
+        doc_b.apply_update_b64(&doc_a.encode_update_b64(&vv_b));
+        assert_eq!(
+            doc_b.poll_line_edits(),
+            vec![LineEdit {
+                start_row: 1,
+                start_col: 1,
+                end_row: 1,
+                end_col: 3,
+                text: String::new(),
+            }]
         );
     }
 }
@@ -1,21 +1,87 @@
-//! End-to-end encryption for session data using AES-256-GCM.
+//! End-to-end encryption for session data using AES-256-GCM or ChaCha20-Poly1305.
 //!
 //! The encryption key is generated locally and shared via the session code.
 //! The server never sees the plaintext data.
+//!
+//! Ciphertext format: `base64url(algo_byte || nonce || ciphertext)`, where the
+//! leading byte identifies the cipher so `decrypt` can pick it automatically.
+//!
+//! Chunked format (`encrypt_chunked`), for payloads too large to seal at once:
+//!
+//! ```text
+//! base64url(algo_byte || count:u32be || chunk_0 || ... || chunk_{count-1})
+//! chunk_i = len:u32be || nonce || ciphertext
+//! ```
+//!
+//! Every chunk uses a fresh nonce and `index:u32be || count:u32be` as its
+//! AAD, so chunks cannot be reordered, dropped or appended without failing
+//! authentication.
+//!
+//! Keys can also be derived from a shared passphrase and salt with Argon2id
+//! (`derive_key`), so only the salt needs to travel in the session code.
 
 use aes_gcm::{
     Aes256Gcm, KeyInit, Nonce,
-    aead::{Aead, OsRng, rand_core::RngCore},
+    aead::{Aead, OsRng, Payload, rand_core::RngCore},
 };
+use argon2::Argon2;
 use base64ct::{Base64UrlUnpadded, Encoding};
+use chacha20poly1305::ChaCha20Poly1305;
 use nvim_oxi::{Dictionary, Function, Object};
+use sha2::{Digest, Sha256};
 
 /// Key size in bytes (256 bits)
 pub const KEY_SIZE: usize = 32;
 
-/// Nonce size in bytes (96 bits for GCM)
+/// Nonce size in bytes (96 bits for both GCM and ChaCha20-Poly1305)
 const NONCE_SIZE: usize = 12;
 
+/// Authentication tag size in bytes (128 bits for both ciphers)
+const TAG_SIZE: usize = 16;
+
+/// Number of SHA-256 bytes shown in a key fingerprint
+const FINGERPRINT_SIZE: usize = 8;
+
+/// Salt size in bytes for passphrase key derivation (128 bits)
+pub const SALT_SIZE: usize = 16;
+
+/// AEAD cipher used for a ciphertext, stored as its first byte.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    const AES_BYTE: u8 = 0x01;
+    const CHACHA_BYTE: u8 = 0x02;
+
+    /// Parse a cipher name as passed from Lua ("aes" or "chacha").
+    /// An empty name selects the default (AES-256-GCM).
+    pub fn from_name(name: &str) -> Result<Self, String> {
+        match name {
+            "" | "aes" => Ok(Cipher::Aes256Gcm),
+            "chacha" => Ok(Cipher::ChaCha20Poly1305),
+            other => Err(format!("Unknown cipher: {other}")),
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => Self::AES_BYTE,
+            Cipher::ChaCha20Poly1305 => Self::CHACHA_BYTE,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Result<Self, String> {
+        match byte {
+            Self::AES_BYTE => Ok(Cipher::Aes256Gcm),
+            Self::CHACHA_BYTE => Ok(Cipher::ChaCha20Poly1305),
+            other => Err(format!("Unknown cipher byte: {other:#04x}")),
+        }
+    }
+}
+
 /// Generate a random 256-bit encryption key.
 /// Returns the key as base64url-encoded string.
 pub fn generate_key() -> String {
@@ -24,15 +90,47 @@ pub fn generate_key() -> String {
     Base64UrlUnpadded::encode_string(&key)
 }
 
-/// Encrypt plaintext using AES-256-GCM.
+/// Generate a random 128-bit salt for `derive_key`.
+/// Returns the salt as base64url-encoded string.
+pub fn generate_salt() -> String {
+    let mut salt = [0u8; SALT_SIZE];
+    OsRng.fill_bytes(&mut salt);
+    Base64UrlUnpadded::encode_string(&salt)
+}
+
+/// Derive a 256-bit key from a passphrase using Argon2id.
 ///
 /// # Arguments
-/// * `key_b64` - Base64url-encoded 256-bit key
-/// * `plaintext` - Data to encrypt
+/// * `passphrase` - Shared human-readable secret
+/// * `salt_b64` - Base64url-encoded salt (see `generate_salt`)
 ///
 /// # Returns
-/// Base64url-encoded ciphertext with nonce prepended (nonce || ciphertext)
-pub fn encrypt(key_b64: &str, plaintext: &[u8]) -> Result<String, String> {
+/// Base64url-encoded 256-bit key usable with `encrypt`/`decrypt`
+pub fn derive_key(passphrase: &str, salt_b64: &str) -> Result<String, String> {
+    if passphrase.is_empty() {
+        return Err("Passphrase cannot be empty".to_string());
+    }
+
+    let salt =
+        Base64UrlUnpadded::decode_vec(salt_b64).map_err(|e| format!("Invalid salt base64: {e}"))?;
+
+    if salt.len() != SALT_SIZE {
+        return Err(format!(
+            "Invalid salt size: expected {SALT_SIZE}, got {}",
+            salt.len()
+        ));
+    }
+
+    let mut key = [0u8; KEY_SIZE];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+        .map_err(|e| format!("Key derivation failed: {e}"))?;
+
+    Ok(Base64UrlUnpadded::encode_string(&key))
+}
+
+/// Decode and validate a base64url-encoded 256-bit key.
+fn decode_key(key_b64: &str) -> Result<Vec<u8>, String> {
     let key_bytes =
         Base64UrlUnpadded::decode_vec(key_b64).map_err(|e| format!("Invalid key base64: {e}"))?;
 
@@ -43,63 +141,258 @@ pub fn encrypt(key_b64: &str, plaintext: &[u8]) -> Result<String, String> {
         ));
     }
 
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-        .map_err(|e| format!("Failed to create cipher: {e}"))?;
+    Ok(key_bytes)
+}
+
+/// Compute a short fingerprint of a key for out-of-band comparison.
+///
+/// Returns the first 8 bytes of SHA-256(key) as dash-grouped hex,
+/// e.g. `ab12-cd34-ef56-7890`. The key itself is not revealed.
+pub fn key_fingerprint(key_b64: &str) -> Result<String, String> {
+    let key_bytes = decode_key(key_b64)?;
+    let digest = Sha256::digest(&key_bytes);
+
+    let groups: Vec<String> = digest[..FINGERPRINT_SIZE]
+        .chunks(2)
+        .map(|pair| format!("{:02x}{:02x}", pair[0], pair[1]))
+        .collect();
+
+    Ok(groups.join("-"))
+}
 
+/// Encrypt `plaintext` under a fresh random nonce, returning `nonce || ciphertext`.
+fn seal(key_bytes: &[u8], cipher: Cipher, plaintext: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
     // Generate random nonce
     let mut nonce_bytes = [0u8; NONCE_SIZE];
     OsRng.fill_bytes(&mut nonce_bytes);
     let nonce = Nonce::from(nonce_bytes);
 
     // Encrypt
-    let ciphertext = cipher
-        .encrypt(&nonce, plaintext)
-        .map_err(|e| format!("Encryption failed: {e}"))?;
+    let payload = Payload {
+        msg: plaintext,
+        aad,
+    };
+    let ciphertext = match cipher {
+        Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key_bytes)
+            .map_err(|e| format!("Failed to create cipher: {e}"))?
+            .encrypt(&nonce, payload),
+        Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new_from_slice(key_bytes)
+            .map_err(|e| format!("Failed to create cipher: {e}"))?
+            .encrypt(&nonce, payload),
+    }
+    .map_err(|e| format!("Encryption failed: {e}"))?;
 
-    // Prepend nonce to ciphertext
     let mut result = Vec::with_capacity(NONCE_SIZE + ciphertext.len());
     result.extend_from_slice(&nonce_bytes);
     result.extend_from_slice(&ciphertext);
+    Ok(result)
+}
+
+/// Decrypt a `nonce || ciphertext` buffer produced by `seal`.
+fn open(key_bytes: &[u8], cipher: Cipher, data: &[u8], aad: &[u8]) -> Result<Vec<u8>, String> {
+    if data.len() < NONCE_SIZE {
+        return Err("Ciphertext too short (missing nonce)".to_string());
+    }
+
+    let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
+    let nonce_array: [u8; NONCE_SIZE] = nonce_bytes.try_into().map_err(|_| "Invalid nonce size")?;
+    let nonce = Nonce::from(nonce_array);
+    let payload = Payload {
+        msg: ciphertext,
+        aad,
+    };
+
+    match cipher {
+        Cipher::Aes256Gcm => Aes256Gcm::new_from_slice(key_bytes)
+            .map_err(|e| format!("Failed to create cipher: {e}"))?
+            .decrypt(&nonce, payload),
+        Cipher::ChaCha20Poly1305 => ChaCha20Poly1305::new_from_slice(key_bytes)
+            .map_err(|e| format!("Failed to create cipher: {e}"))?
+            .decrypt(&nonce, payload),
+    }
+    .map_err(|e| format!("Decryption failed: {e}"))
+}
+
+/// Encrypt plaintext with the given cipher.
+///
+/// # Arguments
+/// * `key_b64` - Base64url-encoded 256-bit key
+/// * `plaintext` - Data to encrypt
+/// * `cipher` - AEAD cipher to use
+/// * `aad` - Associated data bound to the ciphertext (e.g. room id); empty for none
+///
+/// # Returns
+/// Base64url-encoded `algo_byte || nonce || ciphertext`
+pub fn encrypt(
+    key_b64: &str,
+    plaintext: &[u8],
+    cipher: Cipher,
+    aad: &[u8],
+) -> Result<String, String> {
+    let key_bytes = decode_key(key_b64)?;
+    let sealed = seal(&key_bytes, cipher, plaintext, aad)?;
+
+    // Prepend algorithm byte to nonce || ciphertext
+    let mut result = Vec::with_capacity(1 + sealed.len());
+    result.push(cipher.to_byte());
+    result.extend_from_slice(&sealed);
 
     Ok(Base64UrlUnpadded::encode_string(&result))
 }
 
-/// Decrypt ciphertext using AES-256-GCM.
+/// Decrypt ciphertext, detecting the cipher from its leading byte.
 ///
 /// # Arguments
 /// * `key_b64` - Base64url-encoded 256-bit key
-/// * `ciphertext_b64` - Base64url-encoded ciphertext with nonce prepended
+/// * `ciphertext_b64` - Base64url-encoded `algo_byte || nonce || ciphertext`
+/// * `aad` - Associated data the ciphertext was bound to; must match exactly
 ///
 /// # Returns
 /// Decrypted plaintext bytes
-pub fn decrypt(key_b64: &str, ciphertext_b64: &str) -> Result<Vec<u8>, String> {
-    let key_bytes =
-        Base64UrlUnpadded::decode_vec(key_b64).map_err(|e| format!("Invalid key base64: {e}"))?;
+pub fn decrypt(key_b64: &str, ciphertext_b64: &str, aad: &[u8]) -> Result<Vec<u8>, String> {
+    let key_bytes = decode_key(key_b64)?;
 
-    if key_bytes.len() != KEY_SIZE {
-        return Err(format!(
-            "Invalid key size: expected {KEY_SIZE}, got {}",
-            key_bytes.len()
-        ));
+    let data = Base64UrlUnpadded::decode_vec(ciphertext_b64)
+        .map_err(|e| format!("Invalid ciphertext base64: {e}"))?;
+
+    let (&algo, sealed) = data
+        .split_first()
+        .ok_or("Ciphertext too short (missing nonce)")?;
+    open(&key_bytes, Cipher::from_byte(algo)?, sealed, aad)
+}
+
+/// AAD for chunk `index` of `count`, so chunks cannot be reordered,
+/// dropped or spliced between blobs of different lengths.
+fn chunk_aad(index: u32, count: u32) -> [u8; 8] {
+    let mut aad = [0u8; 8];
+    aad[..4].copy_from_slice(&index.to_be_bytes());
+    aad[4..].copy_from_slice(&count.to_be_bytes());
+    aad
+}
+
+/// Encrypt plaintext as a sequence of independently sealed chunks.
+///
+/// Each chunk gets its own random nonce and is bound to its position via
+/// AAD, so the receiver can decrypt chunk by chunk. See the module docs for
+/// the framing format.
+///
+/// # Arguments
+/// * `key_b64` - Base64url-encoded 256-bit key
+/// * `plaintext` - Data to encrypt
+/// * `chunk_size` - Maximum plaintext bytes per chunk (must be non-zero)
+/// * `cipher` - AEAD cipher to use
+///
+/// # Returns
+/// Base64url-encoded framed blob
+pub fn encrypt_chunked(
+    key_b64: &str,
+    plaintext: &[u8],
+    chunk_size: usize,
+    cipher: Cipher,
+) -> Result<String, String> {
+    let key_bytes = decode_key(key_b64)?;
+
+    if chunk_size == 0 {
+        return Err("Chunk size must be positive".to_string());
     }
 
-    let data = Base64UrlUnpadded::decode_vec(ciphertext_b64)
+    // Always emit at least one chunk so empty input is still authenticated
+    let chunks: Vec<&[u8]> = if plaintext.is_empty() {
+        vec![plaintext]
+    } else {
+        plaintext.chunks(chunk_size).collect()
+    };
+    let count = u32::try_from(chunks.len()).map_err(|_| "Too many chunks")?;
+
+    let mut result =
+        Vec::with_capacity(1 + 4 + plaintext.len() + chunks.len() * (4 + NONCE_SIZE + TAG_SIZE));
+    result.push(cipher.to_byte());
+    result.extend_from_slice(&count.to_be_bytes());
+
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        let sealed = seal(&key_bytes, cipher, chunk, &chunk_aad(index as u32, count))?;
+        let len = u32::try_from(sealed.len()).map_err(|_| "Chunk too large")?;
+        result.extend_from_slice(&len.to_be_bytes());
+        result.extend_from_slice(&sealed);
+    }
+
+    Ok(Base64UrlUnpadded::encode_string(&result))
+}
+
+/// Decrypt a blob produced by `encrypt_chunked`.
+///
+/// Fails if any chunk is tampered with, reordered, missing or trailing.
+pub fn decrypt_chunked(key_b64: &str, blob_b64: &str) -> Result<Vec<u8>, String> {
+    let key_bytes = decode_key(key_b64)?;
+
+    let data = Base64UrlUnpadded::decode_vec(blob_b64)
         .map_err(|e| format!("Invalid ciphertext base64: {e}"))?;
 
-    if data.len() < NONCE_SIZE {
-        return Err("Ciphertext too short (missing nonce)".to_string());
+    if data.len() < 5 {
+        return Err("Chunked ciphertext too short (missing header)".to_string());
     }
 
-    let (nonce_bytes, ciphertext) = data.split_at(NONCE_SIZE);
-    let nonce_array: [u8; NONCE_SIZE] = nonce_bytes.try_into().map_err(|_| "Invalid nonce size")?;
-    let nonce = Nonce::from(nonce_array);
+    let cipher = Cipher::from_byte(data[0])?;
+    let count = u32::from_be_bytes([data[1], data[2], data[3], data[4]]);
+
+    let mut plaintext = Vec::new();
+    let mut rest = &data[5..];
+    for index in 0..count {
+        if rest.len() < 4 {
+            return Err(format!("Chunk {index} truncated (missing length)"));
+        }
+        let (len_bytes, tail) = rest.split_at(4);
+        let len =
+            u32::from_be_bytes([len_bytes[0], len_bytes[1], len_bytes[2], len_bytes[3]]) as usize;
+        if tail.len() < len {
+            return Err(format!("Chunk {index} truncated"));
+        }
+        let (sealed, tail) = tail.split_at(len);
+
+        let chunk = open(&key_bytes, cipher, sealed, &chunk_aad(index, count))
+            .map_err(|e| format!("Chunk {index}: {e}"))?;
+        plaintext.extend_from_slice(&chunk);
+        rest = tail;
+    }
+
+    if !rest.is_empty() {
+        return Err("Trailing data after last chunk".to_string());
+    }
+
+    Ok(plaintext)
+}
 
-    let cipher = Aes256Gcm::new_from_slice(&key_bytes)
-        .map_err(|e| format!("Failed to create cipher: {e}"))?;
+/// Decrypt ciphertext and return the raw plaintext as base64url.
+///
+/// Use this instead of `decrypt` via Lua for binary payloads (e.g. Loro
+/// updates) that are not valid UTF-8.
+pub fn decrypt_bytes(key_b64: &str, ciphertext_b64: &str, aad: &[u8]) -> Result<String, String> {
+    decrypt(key_b64, ciphertext_b64, aad).map(|bytes| Base64UrlUnpadded::encode_string(&bytes))
+}
 
-    cipher
-        .decrypt(&nonce, ciphertext)
-        .map_err(|e| format!("Decryption failed: {e}"))
+/// Chunked encryption of a base64url plaintext, for binary payloads from Lua.
+///
+/// `cipher_name` is as for `Cipher::from_name` ("" for the default).
+pub fn encrypt_chunked_bytes(
+    key_b64: &str,
+    plaintext_b64: &str,
+    chunk_size: usize,
+    cipher_name: &str,
+) -> Result<String, String> {
+    let plaintext = Base64UrlUnpadded::decode_vec(plaintext_b64)
+        .map_err(|e| format!("Invalid plaintext base64: {e}"))?;
+    encrypt_chunked(
+        key_b64,
+        &plaintext,
+        chunk_size,
+        Cipher::from_name(cipher_name)?,
+    )
+}
+
+/// Decrypt a chunked blob and return the raw plaintext as base64url,
+/// the inverse of `encrypt_chunked_bytes`.
+pub fn decrypt_chunked_bytes(key_b64: &str, blob_b64: &str) -> Result<String, String> {
+    decrypt_chunked(key_b64, blob_b64).map(|bytes| Base64UrlUnpadded::encode_string(&bytes))
 }
 
 /// Export crypto functions to Lua via nvim-oxi.
@@ -112,27 +405,107 @@ pub fn crypto_ffi() -> Dictionary {
             )),
         ),
         (
-            "encrypt",
+            "generate_salt",
+            Object::from(Function::<(), String>::from_fn(
+                |_| -> Result<String, nvim_oxi::Error> { Ok(generate_salt()) },
+            )),
+        ),
+        (
+            "derive_key",
             Object::from(Function::<(String, String), String>::from_fn(
-                |(key, plaintext)| -> Result<String, nvim_oxi::Error> {
-                    match encrypt(&key, plaintext.as_bytes()) {
-                        Ok(ct) => Ok(ct),
+                |(passphrase, salt)| -> Result<String, nvim_oxi::Error> {
+                    match derive_key(&passphrase, &salt) {
+                        Ok(key) => Ok(key),
                         Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
                     }
                 },
             )),
         ),
         (
-            "decrypt",
+            "encrypt_chunked",
+            Object::from(
+                Function::<(String, String, usize, Option<String>), String>::from_fn(
+                    |(key, plaintext_b64, chunk_size, cipher)| -> Result<String, nvim_oxi::Error> {
+                        // Plaintext is base64url, as large payloads are typically
+                        // binary CRDT state
+                        let cipher = cipher.unwrap_or_default();
+                        match encrypt_chunked_bytes(&key, &plaintext_b64, chunk_size, &cipher) {
+                            Ok(blob) => Ok(blob),
+                            Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                        }
+                    },
+                ),
+            ),
+        ),
+        (
+            "decrypt_chunked",
             Object::from(Function::<(String, String), String>::from_fn(
-                |(key, ciphertext)| -> Result<String, nvim_oxi::Error> {
-                    match decrypt(&key, &ciphertext) {
-                        Ok(bytes) => Ok(String::from_utf8_lossy(&bytes).to_string()),
+                |(key, blob)| -> Result<String, nvim_oxi::Error> {
+                    // Returned as base64url, like decrypt_bytes
+                    match decrypt_chunked_bytes(&key, &blob) {
+                        Ok(plaintext_b64) => Ok(plaintext_b64),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "key_fingerprint",
+            Object::from(Function::<String, String>::from_fn(
+                |key| -> Result<String, nvim_oxi::Error> {
+                    match key_fingerprint(&key) {
+                        Ok(fingerprint) => Ok(fingerprint),
                         Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
                     }
                 },
             )),
         ),
+        (
+            "encrypt",
+            Object::from(Function::<
+                (String, String, Option<String>, Option<String>),
+                String,
+            >::from_fn(
+                |(key, plaintext, cipher, aad)| -> Result<String, nvim_oxi::Error> {
+                    let cipher = cipher.unwrap_or_default();
+                    let aad = aad.unwrap_or_default();
+                    match Cipher::from_name(&cipher).and_then(|cipher| {
+                        encrypt(&key, plaintext.as_bytes(), cipher, aad.as_bytes())
+                    }) {
+                        Ok(ct) => Ok(ct),
+                        Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                    }
+                },
+            )),
+        ),
+        (
+            "decrypt",
+            Object::from(
+                Function::<(String, String, Option<String>), String>::from_fn(
+                    |(key, ciphertext, aad)| -> Result<String, nvim_oxi::Error> {
+                        let aad = aad.unwrap_or_default();
+                        match decrypt(&key, &ciphertext, aad.as_bytes()) {
+                            Ok(bytes) => Ok(String::from_utf8_lossy(&bytes).to_string()),
+                            Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                        }
+                    },
+                ),
+            ),
+        ),
+        (
+            "decrypt_bytes",
+            Object::from(
+                Function::<(String, String, Option<String>), String>::from_fn(
+                    |(key, ciphertext, aad)| -> Result<String, nvim_oxi::Error> {
+                        let aad = aad.unwrap_or_default();
+                        match decrypt_bytes(&key, &ciphertext, aad.as_bytes()) {
+                            Ok(plaintext_b64) => Ok(plaintext_b64),
+                            Err(e) => Err(nvim_oxi::Error::Api(nvim_oxi::api::Error::Other(e))),
+                        }
+                    },
+                ),
+            ),
+        ),
     ])
 }
 
@@ -159,8 +532,8 @@ mod tests {
         let key = generate_key();
         let plaintext = b"Hello, world!";
 
-        let ciphertext = encrypt(&key, plaintext).expect("encrypt");
-        let decrypted = decrypt(&key, &ciphertext).expect("decrypt");
+        let ciphertext = encrypt(&key, plaintext, Cipher::Aes256Gcm, b"").expect("encrypt");
+        let decrypted = decrypt(&key, &ciphertext, b"").expect("decrypt");
 
         assert_eq!(decrypted, plaintext);
     }
@@ -170,15 +543,15 @@ mod tests {
         let key = generate_key();
         let plaintext = b"Same message";
 
-        let ct1 = encrypt(&key, plaintext).expect("encrypt 1");
-        let ct2 = encrypt(&key, plaintext).expect("encrypt 2");
+        let ct1 = encrypt(&key, plaintext, Cipher::Aes256Gcm, b"").expect("encrypt 1");
+        let ct2 = encrypt(&key, plaintext, Cipher::Aes256Gcm, b"").expect("encrypt 2");
 
         // Different nonces should produce different ciphertexts
         assert_ne!(ct1, ct2);
 
         // But both should decrypt to same plaintext
-        assert_eq!(decrypt(&key, &ct1).expect("decrypt 1"), plaintext);
-        assert_eq!(decrypt(&key, &ct2).expect("decrypt 2"), plaintext);
+        assert_eq!(decrypt(&key, &ct1, b"").expect("decrypt 1"), plaintext);
+        assert_eq!(decrypt(&key, &ct2, b"").expect("decrypt 2"), plaintext);
     }
 
     #[test]
@@ -187,8 +560,8 @@ mod tests {
         let key2 = generate_key();
         let plaintext = b"Secret message";
 
-        let ciphertext = encrypt(&key1, plaintext).expect("encrypt");
-        let result = decrypt(&key2, &ciphertext);
+        let ciphertext = encrypt(&key1, plaintext, Cipher::Aes256Gcm, b"").expect("encrypt");
+        let result = decrypt(&key2, &ciphertext, b"");
 
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Decryption failed"));
@@ -199,13 +572,13 @@ mod tests {
         let key = generate_key();
         let plaintext = b"Secret message";
 
-        let ciphertext = encrypt(&key, plaintext).expect("encrypt");
+        let ciphertext = encrypt(&key, plaintext, Cipher::Aes256Gcm, b"").expect("encrypt");
         let mut tampered = Base64UrlUnpadded::decode_vec(&ciphertext).expect("decode");
         let last_idx = tampered.len() - 1;
         tampered[last_idx] ^= 0xFF; // Flip last byte
         let tampered_b64 = Base64UrlUnpadded::encode_string(&tampered);
 
-        let result = decrypt(&key, &tampered_b64);
+        let result = decrypt(&key, &tampered_b64, b"");
         assert!(result.is_err());
     }
 
@@ -214,8 +587,8 @@ mod tests {
         let key = generate_key();
         let plaintext = b"";
 
-        let ciphertext = encrypt(&key, plaintext).expect("encrypt");
-        let decrypted = decrypt(&key, &ciphertext).expect("decrypt");
+        let ciphertext = encrypt(&key, plaintext, Cipher::Aes256Gcm, b"").expect("encrypt");
+        let decrypted = decrypt(&key, &ciphertext, b"").expect("decrypt");
 
         assert_eq!(decrypted, plaintext);
     }
@@ -225,15 +598,15 @@ mod tests {
         let key = generate_key();
         let plaintext = vec![0x42u8; 100_000]; // 100KB
 
-        let ciphertext = encrypt(&key, &plaintext).expect("encrypt");
-        let decrypted = decrypt(&key, &ciphertext).expect("decrypt");
+        let ciphertext = encrypt(&key, &plaintext, Cipher::Aes256Gcm, b"").expect("encrypt");
+        let decrypted = decrypt(&key, &ciphertext, b"").expect("decrypt");
 
         assert_eq!(decrypted, plaintext);
     }
 
     #[test]
     fn test_invalid_key_base64() {
-        let result = encrypt("not-valid-base64!!!", b"test");
+        let result = encrypt("not-valid-base64!!!", b"test", Cipher::Aes256Gcm, b"");
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Invalid key base64"));
     }
@@ -241,8 +614,233 @@ mod tests {
     #[test]
     fn test_invalid_key_size() {
         let short_key = Base64UrlUnpadded::encode_string(&[0u8; 16]); // 128-bit
-        let result = encrypt(&short_key, b"test");
+        let result = encrypt(&short_key, b"test", Cipher::Aes256Gcm, b"");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Invalid key size"));
+    }
+
+    #[test]
+    fn test_chacha_roundtrip() {
+        let key = generate_key();
+        let plaintext = b"Hello, ChaCha!";
+
+        let ciphertext = encrypt(&key, plaintext, Cipher::ChaCha20Poly1305, b"").expect("encrypt");
+        let decrypted = decrypt(&key, &ciphertext, b"").expect("decrypt");
+
+        assert_eq!(decrypted, plaintext);
+    }
+
+    #[test]
+    fn test_algo_byte_prefix() {
+        let key = generate_key();
+
+        let aes = encrypt(&key, b"x", Cipher::Aes256Gcm, b"").expect("encrypt aes");
+        let chacha = encrypt(&key, b"x", Cipher::ChaCha20Poly1305, b"").expect("encrypt chacha");
+
+        let aes = Base64UrlUnpadded::decode_vec(&aes).expect("decode");
+        let chacha = Base64UrlUnpadded::decode_vec(&chacha).expect("decode");
+        assert_eq!(Cipher::from_byte(aes[0]), Ok(Cipher::Aes256Gcm));
+        assert_eq!(Cipher::from_byte(chacha[0]), Ok(Cipher::ChaCha20Poly1305));
+    }
+
+    #[test]
+    fn test_cross_cipher_decrypt_fails() {
+        let key = generate_key();
+        let plaintext = b"Secret message";
+
+        // Relabel a ChaCha ciphertext as AES; authentication must fail
+        let ciphertext = encrypt(&key, plaintext, Cipher::ChaCha20Poly1305, b"").expect("encrypt");
+        let mut relabeled = Base64UrlUnpadded::decode_vec(&ciphertext).expect("decode");
+        relabeled[0] = Cipher::Aes256Gcm.to_byte();
+        let relabeled_b64 = Base64UrlUnpadded::encode_string(&relabeled);
+
+        let result = decrypt(&key, &relabeled_b64, b"");
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Decryption failed"));
+    }
+
+    #[test]
+    fn test_cipher_from_name() {
+        assert_eq!(Cipher::from_name(""), Ok(Cipher::Aes256Gcm));
+        assert_eq!(Cipher::from_name("aes"), Ok(Cipher::Aes256Gcm));
+        assert_eq!(Cipher::from_name("chacha"), Ok(Cipher::ChaCha20Poly1305));
+        assert!(Cipher::from_name("des").is_err());
+    }
+
+    #[test]
+    fn test_derive_key_deterministic() {
+        let salt = generate_salt();
+
+        let key1 = derive_key("correct horse battery staple", &salt).expect("derive 1");
+        let key2 = derive_key("correct horse battery staple", &salt).expect("derive 2");
+
+        assert_eq!(key1, key2);
+        let decoded = Base64UrlUnpadded::decode_vec(&key1).expect("valid base64");
+        assert_eq!(decoded.len(), KEY_SIZE);
+    }
+
+    #[test]
+    fn test_derive_key_different_salts_diverge() {
+        let key1 = derive_key("passphrase", &generate_salt()).expect("derive 1");
+        let key2 = derive_key("passphrase", &generate_salt()).expect("derive 2");
+
+        assert_ne!(key1, key2);
+    }
+
+    #[test]
+    fn test_derived_key_roundtrip() {
+        let key = derive_key("passphrase", &generate_salt()).expect("derive");
+
+        let ciphertext = encrypt(&key, b"Hello", Cipher::Aes256Gcm, b"").expect("encrypt");
+        assert_eq!(decrypt(&key, &ciphertext, b"").expect("decrypt"), b"Hello");
+    }
+
+    #[test]
+    fn test_derive_key_invalid_salt() {
+        let short_salt = Base64UrlUnpadded::encode_string(&[0u8; 4]);
+        let result = derive_key("passphrase", &short_salt);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Invalid salt size"));
+    }
+
+    #[test]
+    fn test_decrypt_bytes_non_utf8_roundtrip() {
+        let key = generate_key();
+        let plaintext: Vec<u8> = vec![0x00, 0xFF, 0xFE, 0x80, 0xC3, 0x28, 0x9F, 0x01];
+        assert!(String::from_utf8(plaintext.clone()).is_err());
+
+        let ciphertext = encrypt(&key, &plaintext, Cipher::ChaCha20Poly1305, b"").expect("encrypt");
+        let plaintext_b64 = decrypt_bytes(&key, &ciphertext, b"").expect("decrypt_bytes");
+        let decoded = Base64UrlUnpadded::decode_vec(&plaintext_b64).expect("decode");
+
+        assert_eq!(decoded, plaintext);
+    }
+
+    #[test]
+    fn test_aad_mismatch_fails() {
+        let key = generate_key();
+        let plaintext = b"Room message";
+
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let ciphertext = encrypt(&key, plaintext, cipher, b"room1").expect("encrypt");
+
+            assert_eq!(
+                decrypt(&key, &ciphertext, b"room1").expect("decrypt"),
+                plaintext
+            );
+            let result = decrypt(&key, &ciphertext, b"room2");
+            assert!(result.is_err());
+            assert!(result.unwrap_err().contains("Decryption failed"));
+            assert!(decrypt(&key, &ciphertext, b"").is_err());
+        }
+    }
+
+    #[test]
+    fn test_key_fingerprint_stable() {
+        let key = generate_key();
+
+        let fp1 = key_fingerprint(&key).expect("fingerprint 1");
+        let fp2 = key_fingerprint(&key).expect("fingerprint 2");
+
+        assert_eq!(fp1, fp2);
+        assert_eq!(fp1.len(), 19); // 4 groups of 4 hex chars + 3 dashes
+        assert!(fp1.chars().all(|c| c == '-' || c.is_ascii_hexdigit()));
+    }
+
+    #[test]
+    fn test_key_fingerprint_differs() {
+        let fp1 = key_fingerprint(&generate_key()).expect("fingerprint 1");
+        let fp2 = key_fingerprint(&generate_key()).expect("fingerprint 2");
+
+        assert_ne!(fp1, fp2);
+    }
+
+    #[test]
+    fn test_key_fingerprint_invalid_key() {
+        let short_key = Base64UrlUnpadded::encode_string(&[0u8; 16]);
+        let result = key_fingerprint(&short_key);
         assert!(result.is_err());
         assert!(result.unwrap_err().contains("Invalid key size"));
+
+        assert!(key_fingerprint("not-valid-base64!!!").is_err());
+    }
+
+    #[test]
+    fn test_chunked_roundtrip_large() {
+        let key = generate_key();
+        let plaintext: Vec<u8> = (0..1024 * 1024).map(|i| (i % 251) as u8).collect();
+
+        for cipher in [Cipher::Aes256Gcm, Cipher::ChaCha20Poly1305] {
+            let blob = encrypt_chunked(&key, &plaintext, 64 * 1024, cipher).expect("encrypt");
+            let decrypted = decrypt_chunked(&key, &blob).expect("decrypt");
+            assert_eq!(decrypted, plaintext);
+        }
+    }
+
+    #[test]
+    fn test_chunked_bytes_roundtrip() {
+        let key = generate_key();
+        let data: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let data_b64 = Base64UrlUnpadded::encode_string(&data);
+
+        for cipher in ["", "chacha"] {
+            let blob = encrypt_chunked_bytes(&key, &data_b64, 64, cipher).unwrap();
+            assert_eq!(decrypt_chunked_bytes(&key, &blob).unwrap(), data_b64);
+        }
+
+        assert!(encrypt_chunked_bytes(&key, "not base64!", 64, "").is_err());
+        assert!(encrypt_chunked_bytes(&key, &data_b64, 64, "rot13").is_err());
+    }
+
+    #[test]
+    fn test_chunked_reorder_fails() {
+        let key = generate_key();
+        let plaintext = vec![0x42u8; 1024 * 1024];
+        let chunk_size = 64 * 1024;
+
+        let blob =
+            encrypt_chunked(&key, &plaintext, chunk_size, Cipher::Aes256Gcm).expect("encrypt");
+        let mut data = Base64UrlUnpadded::decode_vec(&blob).expect("decode");
+
+        // All chunks are full-sized, so each frame has the same length
+        let frame_len = 4 + NONCE_SIZE + chunk_size + TAG_SIZE;
+        let (first, second) = (5, 5 + frame_len);
+        let chunk0 = data[first..second].to_vec();
+        let chunk1 = data[second..second + frame_len].to_vec();
+        data[first..second].copy_from_slice(&chunk1);
+        data[second..second + frame_len].copy_from_slice(&chunk0);
+        let swapped = Base64UrlUnpadded::encode_string(&data);
+
+        let result = decrypt_chunked(&key, &swapped);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().contains("Decryption failed"));
+    }
+
+    #[test]
+    fn test_chunked_truncation_fails() {
+        let key = generate_key();
+        let plaintext = vec![0x42u8; 1000];
+
+        let blob = encrypt_chunked(&key, &plaintext, 100, Cipher::Aes256Gcm).expect("encrypt");
+        let mut data = Base64UrlUnpadded::decode_vec(&blob).expect("decode");
+
+        // Drop the last chunk and patch the count to match
+        let frame_len = 4 + NONCE_SIZE + 100 + TAG_SIZE;
+        data.truncate(data.len() - frame_len);
+        data[1..5].copy_from_slice(&9u32.to_be_bytes());
+        let truncated = Base64UrlUnpadded::encode_string(&data);
+
+        assert!(decrypt_chunked(&key, &truncated).is_err());
+    }
+
+    #[test]
+    fn test_chunked_empty_and_zero_chunk_size() {
+        let key = generate_key();
+
+        let blob = encrypt_chunked(&key, b"", 16, Cipher::Aes256Gcm).expect("encrypt");
+        assert_eq!(decrypt_chunked(&key, &blob).expect("decrypt"), b"");
+
+        let result = encrypt_chunked(&key, b"data", 0, Cipher::Aes256Gcm);
+        assert!(result.is_err());
     }
 }
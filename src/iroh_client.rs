@@ -6,7 +6,10 @@
 //! QUIC/TLS 1.3 provides E2E encryption automatically - no manual crypto needed.
 
 use base64::Engine;
-use iroh::{Endpoint, EndpointAddr, RelayMode, RelayUrl, SecretKey, TransportAddr};
+use iroh::{
+    Endpoint, EndpointAddr, RelayMap, RelayMode, RelayUrl, SecretKey, TransportAddr, Watcher,
+    endpoint::{ConnectOptions, ConnectionType},
+};
 use log::{debug, error, info, warn};
 use nvim_oxi::{
     Dictionary, Function, Object,
@@ -18,27 +21,99 @@ use nvim_oxi::{
     schedule,
 };
 use parking_lot::Mutex;
-use std::{collections::HashMap, sync::Arc, sync::LazyLock};
-use tokio::sync::{
-    mpsc::{self, UnboundedReceiver, UnboundedSender},
-    oneshot,
+use std::{
+    collections::{BTreeSet, HashMap},
+    sync::Arc,
+    sync::LazyLock,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    sync::{
+        mpsc::{self, UnboundedReceiver, UnboundedSender},
+        oneshot,
+    },
 };
 use uuid::Uuid;
 
 use crate::runtime;
 
-/// ALPN protocol identifier for tandem CRDT sync
-const TANDEM_ALPN: &[u8] = b"tandem/crdt/1";
+/// ALPN protocol identifiers for tandem CRDT sync, newest first. Both sides offer
+/// all of them and the host's TLS handshake picks the first one the peer supports.
+///
+/// - v1: full state, update and presence frames
+/// - v2: adds the bye frame (`MSG_BYE`) announcing a clean close
+const TANDEM_ALPNS: &[&[u8]] = &[b"tandem/crdt/2", b"tandem/crdt/1"];
+
+/// Prefix of the tandem ALPN identifiers, followed by the wire format version
+const TANDEM_ALPN_PREFIX: &str = "tandem/crdt/";
+
+/// The ALPN list endpoints are built with
+fn tandem_alpns() -> Vec<Vec<u8>> {
+    TANDEM_ALPNS.iter().map(|alpn| alpn.to_vec()).collect()
+}
+
+/// Wire format version of a negotiated ALPN (0 if it is not a tandem ALPN)
+fn protocol_version(alpn: &[u8]) -> u32 {
+    std::str::from_utf8(alpn)
+        .ok()
+        .and_then(|alpn| alpn.strip_prefix(TANDEM_ALPN_PREFIX))
+        .and_then(|version| version.parse().ok())
+        .unwrap_or(0)
+}
+
+/// How long a relay-less host waits for a local direct address before reporting Ready
+const DIRECT_ADDR_WAIT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// MAX_FRAME_LEN value meaning the frame limit follows the Lua payload limit
+const FRAME_LEN_FROM_UPDATE_LIMIT: usize = usize::MAX;
+
+/// Frame limit set by iroh_set_max_frame_size (0 disables the check)
+static MAX_FRAME_LEN: AtomicUsize = AtomicUsize::new(FRAME_LEN_FROM_UPDATE_LIMIT);
+
+/// Largest raw payload whose base64 form fits in `b64_len` bytes
+fn raw_len_for_b64(b64_len: usize) -> usize {
+    b64_len / 4 * 3
+}
+
+/// Current limit on wire frame payloads (0 if disabled). Unless overridden, frames
+/// are capped so their base64 form still passes the Lua payload limit; larger
+/// ones would only be read to be rejected by doc_apply_update.
+fn max_frame_len() -> usize {
+    match MAX_FRAME_LEN.load(Ordering::Relaxed) {
+        FRAME_LEN_FROM_UPDATE_LIMIT => raw_len_for_b64(crate::crdt::max_update_b64_len()),
+        max => max,
+    }
+}
+
+/// How long a frame may go without any bytes arriving once its type byte was read
+const FRAME_READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
 
 /// Message type constants for wire protocol
 const MSG_FULL_STATE: u8 = 0x01;
 const MSG_UPDATE: u8 = 0x02;
 const MSG_PRESENCE: u8 = 0x03;
+/// Empty frame announcing a clean close; the receiver disconnects without error.
+/// Only sent to peers that negotiated protocol v2 or later.
+const MSG_BYE: u8 = 0x04;
+
+/// Whether a peer on the given protocol version understands the bye frame
+fn supports_bye(version: u32) -> bool {
+    version >= 2
+}
+
+/// How long the closing side waits for the peer to read its bye frame
+const BYE_FLUSH_WAIT: std::time::Duration = std::time::Duration::from_millis(500);
 
 /// Global registry of Iroh clients
 static CLIENTS: LazyLock<Mutex<HashMap<Uuid, IrohClient>>> =
     LazyLock::new(|| Mutex::new(HashMap::new()));
 
+/// Event queues for clients created in polling mode.
+/// Kept separate from CLIENTS so final events survive the client's removal.
+static POLLED_EVENTS: LazyLock<Mutex<HashMap<Uuid, UnboundedReceiver<IrohEvent>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
 /// Events received from Iroh P2P
 #[derive(Debug, Clone)]
 pub enum IrohEvent {
@@ -46,9 +121,14 @@ pub enum IrohEvent {
     Ready {
         endpoint_id: String,
         relay_url: String,
+        /// Direct socket addresses (for LAN session codes)
+        direct_addrs: Vec<String>,
+    },
+    /// A peer connected, with the negotiated wire format version
+    PeerConnected {
+        peer_id: String,
+        protocol_version: u32,
     },
-    /// A peer connected (host only)
-    PeerConnected { peer_id: String },
     /// A peer disconnected
     PeerDisconnected { peer_id: String },
     /// Received full CRDT state (base64 encoded)
@@ -61,15 +141,118 @@ pub enum IrohEvent {
     Error(String),
 }
 
+impl IrohEvent {
+    /// Ready event for our own endpoint address: its first relay URL (empty if
+    /// none) and every direct IP address, for LAN session codes
+    fn ready(addr: &EndpointAddr) -> Self {
+        IrohEvent::Ready {
+            endpoint_id: addr.id.to_string(),
+            relay_url: addr
+                .relay_urls()
+                .next()
+                .map(|u| u.to_string())
+                .unwrap_or_default(),
+            direct_addrs: direct_addr_strings(addr),
+        }
+    }
+
+    /// Serialize to JSON string for polling mode
+    fn to_json(&self) -> String {
+        let value = match self {
+            IrohEvent::Ready {
+                endpoint_id,
+                relay_url,
+                direct_addrs,
+            } => serde_json::json!({
+                "type": "ready",
+                "endpoint_id": endpoint_id,
+                "relay_url": relay_url,
+                "direct_addrs": direct_addrs,
+            }),
+            IrohEvent::PeerConnected {
+                peer_id,
+                protocol_version,
+            } => serde_json::json!({
+                "type": "peer_connected",
+                "peer_id": peer_id,
+                "protocol_version": protocol_version,
+            }),
+            IrohEvent::PeerDisconnected { peer_id } => {
+                serde_json::json!({ "type": "peer_disconnected", "peer_id": peer_id })
+            }
+            IrohEvent::FullState(data) => {
+                serde_json::json!({ "type": "full_state", "data": data })
+            }
+            IrohEvent::Update(data) => serde_json::json!({ "type": "update", "data": data }),
+            IrohEvent::Presence { peer_id, data } => {
+                serde_json::json!({ "type": "presence", "peer_id": peer_id, "data": data })
+            }
+            IrohEvent::Error(err) => serde_json::json!({ "type": "error", "error": err }),
+        };
+        value.to_string()
+    }
+}
+
+/// Wakes the Lua side when events are queued.
+/// In polling mode there is no AsyncHandle and events wait in the channel.
+#[derive(Clone)]
+struct EventNotifier(Option<AsyncHandle>);
+
+impl EventNotifier {
+    fn send(&self) -> Result<(), nvim_oxi::libuv::Error> {
+        match &self.0 {
+            Some(handle) => handle.send(),
+            None => Ok(()),
+        }
+    }
+}
+
 /// Outbound message types
 #[derive(Debug, Clone)]
 enum OutboundMsg {
     /// Send full CRDT state to peer
     FullState(Vec<u8>),
+    /// Full CRDT state as base64 from Lua, decoded in the runtime task
+    FullStateB64(String),
     /// Send incremental CRDT update
     Update(Vec<u8>),
+    /// Incremental CRDT update as base64 from Lua, decoded in the runtime task
+    UpdateB64(String),
     /// Send presence/cursor update (JSON bytes)
     Presence(Vec<u8>),
+    /// Say goodbye before the host closes
+    Bye,
+}
+
+/// Decode a base64 CRDT payload queued by Lua
+fn decode_payload(data_b64: &str) -> Result<Vec<u8>, String> {
+    base64::engine::general_purpose::STANDARD
+        .decode(data_b64)
+        .map_err(|e| format!("Invalid base64 data: {}", e))
+}
+
+impl OutboundMsg {
+    /// Decode base64 payloads into raw ones, so a broadcast is decoded once
+    /// rather than per peer. Other messages are returned unchanged.
+    fn decoded(self) -> Result<Self, String> {
+        Ok(match self {
+            OutboundMsg::FullStateB64(b64) => OutboundMsg::FullState(decode_payload(&b64)?),
+            OutboundMsg::UpdateB64(b64) => OutboundMsg::Update(decode_payload(&b64)?),
+            msg => msg,
+        })
+    }
+
+    /// Wire message type and payload
+    fn into_frame(self) -> Result<(u8, Vec<u8>), String> {
+        Ok(match self {
+            OutboundMsg::FullState(d) => (MSG_FULL_STATE, d),
+            OutboundMsg::FullStateB64(b64) => (MSG_FULL_STATE, decode_payload(&b64)?),
+            OutboundMsg::Update(d) => (MSG_UPDATE, d),
+            OutboundMsg::UpdateB64(b64) => (MSG_UPDATE, decode_payload(&b64)?),
+            OutboundMsg::Presence(d) => (MSG_PRESENCE, d),
+            OutboundMsg::Bye => (MSG_BYE, Vec::new()),
+        })
+    }
 }
 
 /// Helper to invoke a Lua callback by name from the global registry
@@ -101,34 +284,95 @@ fn invoke_callback(client_id: &str, callback_name: &str, args: impl nvim_oxi::ml
     }
 }
 
+/// Live connection info for `iroh_stats`, updated by the client's task.
+#[derive(Default)]
+struct ConnState {
+    /// Set once the endpoint is bound; used to look up each peer's path
+    endpoint: Option<Endpoint>,
+    /// Endpoint IDs of currently connected peers
+    peers: BTreeSet<String>,
+}
+
+type SharedConnState = Arc<Mutex<ConnState>>;
+
+/// Short name for how we reach a peer: direct (hole-punched), relay, mixed or none.
+fn path_kind(conn_type: &ConnectionType) -> &'static str {
+    match conn_type {
+        ConnectionType::Direct(_) => "direct",
+        ConnectionType::Relay(_) => "relay",
+        ConnectionType::Mixed(_, _) => "mixed",
+        ConnectionType::None => "none",
+    }
+}
+
+impl ConnState {
+    /// Serialize to JSON: endpoint id, peer count and each peer's path type
+    fn to_json(&self) -> String {
+        let peers: Vec<serde_json::Value> = self
+            .peers
+            .iter()
+            .map(|peer_id| {
+                let path = self
+                    .endpoint
+                    .as_ref()
+                    .zip(peer_id.parse::<iroh::EndpointId>().ok())
+                    .and_then(|(endpoint, peer)| endpoint.conn_type(peer))
+                    .map(|mut watcher| path_kind(&watcher.get()))
+                    .unwrap_or("unknown");
+                serde_json::json!({ "peer_id": peer_id, "path": path })
+            })
+            .collect();
+
+        serde_json::json!({
+            "endpoint_id": self.endpoint.as_ref().map(|e| e.id().to_string()).unwrap_or_default(),
+            "peer_count": self.peers.len(),
+            "peers": peers,
+        })
+        .to_string()
+    }
+}
+
 /// An Iroh P2P client instance
 struct IrohClient {
     id: Uuid,
     outbound_tx: UnboundedSender<OutboundMsg>,
     close_tx: UnboundedSender<()>,
+    /// Connection info shared with the async task
+    state: SharedConnState,
     /// Kept alive to receive async notifications (not directly accessed)
-    _lua_handle: AsyncHandle,
+    _notifier: EventNotifier,
 }
 
 impl IrohClient {
-    fn new_host(client_id: Uuid) -> Result<Self, String> {
+    fn new_host(client_id: Uuid, polling: bool, relay_mode: RelayMode) -> Result<Self, String> {
         info!("[iroh:{}] Creating host client", client_id);
-        Self::new(client_id, true, None)
+        Self::new(client_id, true, None, polling, relay_mode)
     }
 
-    fn new_joiner(client_id: Uuid, session_code: String) -> Result<Self, String> {
+    fn new_joiner(
+        client_id: Uuid,
+        session_code: String,
+        polling: bool,
+        relay_mode: RelayMode,
+    ) -> Result<Self, String> {
         info!("[iroh:{}] Creating joiner client", client_id);
-        Self::new(client_id, false, Some(session_code))
+        Self::new(client_id, false, Some(session_code), polling, relay_mode)
     }
 
-    fn new(client_id: Uuid, is_host: bool, session_code: Option<String>) -> Result<Self, String> {
+    fn new(
+        client_id: Uuid,
+        is_host: bool,
+        session_code: Option<String>,
+        polling: bool,
+        relay_mode: RelayMode,
+    ) -> Result<Self, String> {
         info!(
-            "[iroh:{}] Initializing client (is_host={})",
-            client_id, is_host
+            "[iroh:{}] Initializing client (is_host={}, polling={})",
+            client_id, is_host, polling
         );
 
-        // Channel for inbound events (from Iroh task to AsyncHandle)
-        let (inbound_tx, mut inbound_rx) = mpsc::unbounded_channel::<IrohEvent>();
+        // Channel for inbound events (from Iroh task to AsyncHandle or poll queue)
+        let (inbound_tx, inbound_rx) = mpsc::unbounded_channel::<IrohEvent>();
 
         // Channel for outbound messages (from FFI to Iroh task)
         let (outbound_tx, outbound_rx) = mpsc::unbounded_channel::<OutboundMsg>();
@@ -136,6 +380,78 @@ impl IrohClient {
         // Channel for close signal
         let (close_tx, close_rx) = mpsc::unbounded_channel::<()>();
 
+        let notifier = if polling {
+            // Events stay queued until Lua calls poll_events
+            POLLED_EVENTS.lock().insert(client_id, inbound_rx);
+            EventNotifier(None)
+        } else {
+            EventNotifier(Some(Self::create_lua_handle(client_id, inbound_rx)?))
+        };
+
+        let state = SharedConnState::default();
+
+        // Clone for async task
+        let lua_handle_clone = notifier.clone();
+        let inbound_tx_clone = inbound_tx.clone();
+        let state_clone = state.clone();
+        let id = client_id;
+
+        // Spawn Iroh task
+        runtime().spawn(async move {
+            info!("[iroh:{}] Async task started", id);
+            let result = if is_host {
+                run_host(
+                    id,
+                    inbound_tx_clone.clone(),
+                    &lua_handle_clone,
+                    outbound_rx,
+                    close_rx,
+                    state_clone,
+                    relay_mode,
+                )
+                .await
+            } else {
+                let code = session_code.expect("session_code required for joiner");
+                run_joiner(
+                    id,
+                    code,
+                    inbound_tx_clone.clone(),
+                    &lua_handle_clone,
+                    outbound_rx,
+                    close_rx,
+                    state_clone,
+                    relay_mode,
+                )
+                .await
+            };
+
+            if let Err(e) = result {
+                error!("[iroh:{}] Error: {}", id, e);
+                let _ = inbound_tx_clone.send(IrohEvent::Error(e.to_string()));
+                let _ = lua_handle_clone.send();
+            }
+
+            // Remove from registry
+            CLIENTS.lock().remove(&id);
+            info!("[iroh:{}] Client removed from registry", id);
+        });
+
+        info!("[iroh:{}] Client initialization complete", client_id);
+
+        Ok(Self {
+            id: client_id,
+            outbound_tx,
+            close_tx,
+            state,
+            _notifier: notifier,
+        })
+    }
+
+    /// Create the AsyncHandle that delivers queued events to Lua callbacks
+    fn create_lua_handle(
+        client_id: Uuid,
+        mut inbound_rx: UnboundedReceiver<IrohEvent>,
+    ) -> Result<AsyncHandle, String> {
         // Create AsyncHandle that will invoke Lua callbacks when events arrive
         // Callbacks are looked up lazily inside schedule() to avoid holding LuaFunction across threads
         let id_str = client_id.to_string();
@@ -170,11 +486,23 @@ impl IrohClient {
                         IrohEvent::Ready {
                             endpoint_id,
                             relay_url,
+                            direct_addrs,
                         } => {
-                            invoke_callback(&id, "on_ready", (id.clone(), endpoint_id, relay_url));
+                            invoke_callback(
+                                &id,
+                                "on_ready",
+                                (id.clone(), endpoint_id, relay_url, direct_addrs),
+                            );
                         }
-                        IrohEvent::PeerConnected { peer_id } => {
-                            invoke_callback(&id, "on_peer_connected", (id.clone(), peer_id));
+                        IrohEvent::PeerConnected {
+                            peer_id,
+                            protocol_version,
+                        } => {
+                            invoke_callback(
+                                &id,
+                                "on_peer_connected",
+                                (id.clone(), peer_id, protocol_version),
+                            );
                         }
                         IrohEvent::PeerDisconnected { peer_id } => {
                             invoke_callback(&id, "on_peer_disconnected", (id.clone(), peer_id));
@@ -201,66 +529,17 @@ impl IrohClient {
         .map_err(|e| format!("Failed to create AsyncHandle: {}", e))?;
 
         info!("[iroh:{}] AsyncHandle created", client_id);
-
-        // Clone for async task
-        let lua_handle_clone = lua_handle.clone();
-        let inbound_tx_clone = inbound_tx.clone();
-        let id = client_id;
-
-        // Spawn Iroh task
-        runtime().spawn(async move {
-            info!("[iroh:{}] Async task started", id);
-            let result = if is_host {
-                run_host(
-                    id,
-                    inbound_tx_clone.clone(),
-                    &lua_handle_clone,
-                    outbound_rx,
-                    close_rx,
-                )
-                .await
-            } else {
-                let code = session_code.expect("session_code required for joiner");
-                run_joiner(
-                    id,
-                    code,
-                    inbound_tx_clone.clone(),
-                    &lua_handle_clone,
-                    outbound_rx,
-                    close_rx,
-                )
-                .await
-            };
-
-            if let Err(e) = result {
-                error!("[iroh:{}] Error: {}", id, e);
-                let _ = inbound_tx_clone.send(IrohEvent::Error(e.to_string()));
-                let _ = lua_handle_clone.send();
-            }
-
-            // Remove from registry
-            CLIENTS.lock().remove(&id);
-            info!("[iroh:{}] Client removed from registry", id);
-        });
-
-        info!("[iroh:{}] Client initialization complete", client_id);
-
-        Ok(Self {
-            id: client_id,
-            outbound_tx,
-            close_tx,
-            _lua_handle: lua_handle,
-        })
+        Ok(lua_handle)
     }
 
-    fn send_full_state(&self, data: Vec<u8>) {
-        if let Err(e) = self.outbound_tx.send(OutboundMsg::FullState(data)) {
+    fn send_full_state(&self, data_b64: String) {
+        if let Err(e) = self.outbound_tx.send(OutboundMsg::FullStateB64(data_b64)) {
             error!("[iroh:{}] Failed to queue full state: {}", self.id, e);
         }
     }
 
-    fn send_update(&self, data: Vec<u8>) {
-        if let Err(e) = self.outbound_tx.send(OutboundMsg::Update(data)) {
+    fn send_update(&self, data_b64: String) {
+        if let Err(e) = self.outbound_tx.send(OutboundMsg::UpdateB64(data_b64)) {
             error!("[iroh:{}] Failed to queue update: {}", self.id, e);
         }
     }
@@ -280,9 +559,11 @@ impl IrohClient {
 async fn run_host(
     id: Uuid,
     event_tx: UnboundedSender<IrohEvent>,
-    lua_handle: &AsyncHandle,
+    lua_handle: &EventNotifier,
     mut outbound_rx: UnboundedReceiver<OutboundMsg>,
     mut close_rx: UnboundedReceiver<()>,
+    state: SharedConnState,
+    relay_mode: RelayMode,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!("[iroh:{}] Starting host endpoint", id);
 
@@ -299,33 +580,20 @@ async fn run_host(
     let secret_key = SecretKey::generate(&mut rand::rng());
 
     // Build endpoint
+    let relay_disabled = matches!(relay_mode, RelayMode::Disabled);
     let endpoint = Endpoint::builder()
         .secret_key(secret_key)
-        .alpns(vec![TANDEM_ALPN.to_vec()])
-        .relay_mode(RelayMode::Default)
+        .alpns(tandem_alpns())
+        .relay_mode(relay_mode)
         .bind()
         .await?;
 
-    // Wait for endpoint to be online
-    endpoint.online().await;
+    wait_until_reachable(&endpoint, relay_disabled).await;
+    state.lock().endpoint = Some(endpoint.clone());
 
-    let endpoint_id = endpoint.id().to_string();
-    let endpoint_addr = endpoint.addr();
-    let relay_url = endpoint_addr
-        .relay_urls()
-        .next()
-        .map(|u| u.to_string())
-        .unwrap_or_default();
-
-    info!(
-        "[iroh:{}] Host ready: endpoint_id={}, relay_url={}",
-        id, endpoint_id, relay_url
-    );
-
-    send_event(IrohEvent::Ready {
-        endpoint_id,
-        relay_url,
-    });
+    let ready = IrohEvent::ready(&endpoint.addr());
+    info!("[iroh:{}] Host ready: {}", id, ready.to_json());
+    send_event(ready);
 
     // Track connected peers and their send channels
     let peers: Arc<Mutex<HashMap<String, UnboundedSender<OutboundMsg>>>> =
@@ -352,6 +620,8 @@ async fn run_host(
                             // Clone for the connection handler
                             let peer_id_holder_for_handler = peer_id_holder.clone();
                             let peers_for_handler = peers.clone();
+                            let own_tx = peer_tx.clone();
+                            let state_for_handler = state.clone();
 
                             tokio::spawn(async move {
                                 if let Err(e) = handle_peer_connection(
@@ -362,12 +632,15 @@ async fn run_host(
                                     peer_rx,
                                     peer_id_holder_for_handler.clone(),
                                     peer_id_tx,
+                                    peers_for_handler.clone(),
+                                    own_tx,
                                 ).await {
                                     error!("[iroh:{}] Peer connection error: {}", host_id, e);
                                 }
                                 // Cleanup: remove from peers map
                                 if let Some(peer_id) = peer_id_holder_for_handler.lock().take() {
                                     peers_for_handler.lock().remove(&peer_id);
+                                    state_for_handler.lock().peers.remove(&peer_id);
                                 }
                             });
 
@@ -377,10 +650,12 @@ async fn run_host(
 
                             // Spawn task to update the key once peer_id is signaled
                             let peers_for_update = peers.clone();
+                            let state_for_update = state.clone();
                             tokio::spawn(async move {
                                 // Wait for peer_id signal (no timing assumptions)
                                 match peer_id_rx.await {
                                     Ok(real_peer_id) => {
+                                        state_for_update.lock().peers.insert(real_peer_id.clone());
                                         let mut peers_guard = peers_for_update.lock();
                                         if let Some(tx) = peers_guard.remove(&temp_key) {
                                             peers_guard.insert(real_peer_id, tx);
@@ -404,6 +679,13 @@ async fn run_host(
             // Handle outbound messages (broadcast to all peers)
             msg = outbound_rx.recv() => {
                 if let Some(msg) = msg {
+                    let msg = match msg.decoded() {
+                        Ok(msg) => msg,
+                        Err(e) => {
+                            error!("[iroh:{}] Not sending: {}", id, e);
+                            continue;
+                        }
+                    };
                     let peers_guard = peers.lock();
                     for (peer_id, tx) in peers_guard.iter() {
                         if let Err(e) = tx.send(msg.clone()) {
@@ -421,37 +703,86 @@ async fn run_host(
         }
     }
 
+    // Say bye to every peer; each handler removes itself once its bye is flushed
+    for tx in peers.lock().values() {
+        let _ = tx.send(OutboundMsg::Bye);
+    }
+    let deadline = tokio::time::Instant::now() + BYE_FLUSH_WAIT;
+    while !peers.lock().is_empty() && tokio::time::Instant::now() < deadline {
+        tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+    }
+
     endpoint.close().await;
     Ok(())
 }
 
+/// Wait for the endpoint to be reachable. online() waits for a relay, which never
+/// comes in LAN-only mode, so wait for a direct address instead.
+async fn wait_until_reachable(endpoint: &Endpoint, relay_disabled: bool) {
+    if relay_disabled {
+        let deadline = tokio::time::Instant::now() + DIRECT_ADDR_WAIT;
+        while endpoint.addr().ip_addrs().next().is_none() && tokio::time::Instant::now() < deadline
+        {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+    } else {
+        endpoint.online().await;
+    }
+}
+
+/// Decode a frame's length prefix, rejecting lengths over the configured limit
+/// before anything is allocated for the payload.
+fn frame_len(len_buf: [u8; 4]) -> Result<usize, String> {
+    let len = u32::from_be_bytes(len_buf) as usize;
+    let max = max_frame_len();
+    if max > 0 && len > max {
+        return Err(format!(
+            "frame of {} bytes exceeds the {} byte limit",
+            len, max
+        ));
+    }
+    Ok(len)
+}
+
 /// Read a typed, length-prefixed message from stream
 /// Returns (message_type, data)
 async fn read_message(
-    recv: &mut iroh::endpoint::RecvStream,
+    recv: &mut (impl AsyncRead + Unpin),
 ) -> Result<(u8, Vec<u8>), Box<dyn std::error::Error + Send + Sync>> {
-    // Read message type (1 byte)
+    // Read message type (1 byte). Waiting here is normal: peers may be idle.
     let mut type_buf = [0u8; 1];
     recv.read_exact(&mut type_buf).await?;
     let msg_type = type_buf[0];
 
+    // Once a frame has started, a peer that stalls mid-frame is dropped. The timeout
+    // applies per read, so a large frame on a slow link is fine while bytes arrive.
+    let stalled = || format!("timed out reading frame of type {}", msg_type);
+
     // Read length (4 bytes)
     let mut len_buf = [0u8; 4];
-    recv.read_exact(&mut len_buf).await?;
-    let len = u32::from_be_bytes(len_buf) as usize;
+    tokio::time::timeout(FRAME_READ_TIMEOUT, recv.read_exact(&mut len_buf))
+        .await
+        .map_err(|_| stalled())??;
+    let len = frame_len(len_buf)?;
 
-    if len == 0 {
-        return Ok((msg_type, Vec::new()));
+    let mut data = vec![0u8; len];
+    let mut filled = 0;
+    while filled < len {
+        let n = tokio::time::timeout(FRAME_READ_TIMEOUT, recv.read(&mut data[filled..]))
+            .await
+            .map_err(|_| stalled())??;
+        if n == 0 {
+            return Err(format!("stream ended inside frame of type {}", msg_type).into());
+        }
+        filled += n;
     }
 
-    let mut data = vec![0u8; len];
-    recv.read_exact(&mut data).await?;
     Ok((msg_type, data))
 }
 
 /// Write a typed, length-prefixed message to stream
 async fn write_message(
-    send: &mut iroh::endpoint::SendStream,
+    send: &mut (impl AsyncWrite + Unpin),
     msg_type: u8,
     data: &[u8],
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
@@ -466,20 +797,55 @@ async fn write_message(
     Ok(())
 }
 
+/// Send a bye frame, finish the stream and give the peer a moment to read it
+/// before the connection is torn down.
+async fn send_bye(
+    send: &mut iroh::endpoint::SendStream,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    write_message(send, MSG_BYE, &[]).await?;
+    send.finish()?;
+    // Resolves once the peer has read the stream to the end or stopped it
+    let _ = tokio::time::timeout(BYE_FLUSH_WAIT, send.stopped()).await;
+    Ok(())
+}
+
+/// Send `msg` to every peer channel except `sender`, so the host relays
+/// updates between joiners. Returns the number of peers it was sent to.
+fn forward_to_other_peers(
+    peers: &Mutex<HashMap<String, UnboundedSender<OutboundMsg>>>,
+    sender: &UnboundedSender<OutboundMsg>,
+    msg: &OutboundMsg,
+) -> usize {
+    let peers_guard = peers.lock();
+    peers_guard
+        .values()
+        // Compare channels, not keys: the sender may still be under its pending_ key
+        .filter(|tx| !tx.same_channel(sender))
+        .filter(|tx| tx.send(msg.clone()).is_ok())
+        .count()
+}
+
 /// Handle a peer connection (host side)
+#[allow(clippy::too_many_arguments)]
 async fn handle_peer_connection(
     host_id: Uuid,
     accepting: iroh::endpoint::Accepting,
     event_tx: UnboundedSender<IrohEvent>,
-    lua_handle: &AsyncHandle,
+    lua_handle: &EventNotifier,
     mut peer_rx: UnboundedReceiver<OutboundMsg>,
     peer_id_out: Arc<Mutex<Option<String>>>,
     peer_id_tx: oneshot::Sender<String>,
+    peers: Arc<Mutex<HashMap<String, UnboundedSender<OutboundMsg>>>>,
+    own_tx: UnboundedSender<OutboundMsg>,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let conn = accepting.await?;
     let peer_id = conn.remote_id().to_string();
+    let version = protocol_version(conn.alpn());
 
-    info!("[iroh:{}] Peer connected: {}", host_id, peer_id);
+    info!(
+        "[iroh:{}] Peer connected: {} (protocol v{})",
+        host_id, peer_id, version
+    );
 
     // Store peer_id so caller can clean up
     *peer_id_out.lock() = Some(peer_id.clone());
@@ -490,6 +856,7 @@ async fn handle_peer_connection(
     // Notify Lua - this triggers on_peer_connected which calls send_full_state
     let _ = event_tx.send(IrohEvent::PeerConnected {
         peer_id: peer_id.clone(),
+        protocol_version: version,
     });
     let _ = lua_handle.send();
 
@@ -505,11 +872,7 @@ async fn handle_peer_connection(
 
     match initial {
         Ok(Some(msg)) => {
-            let (msg_type, data) = match msg {
-                OutboundMsg::FullState(d) => (MSG_FULL_STATE, d),
-                OutboundMsg::Update(d) => (MSG_UPDATE, d),
-                OutboundMsg::Presence(d) => (MSG_PRESENCE, d),
-            };
+            let (msg_type, data) = msg.into_frame()?;
             info!(
                 "[iroh:{}] Sending initial state to peer ({} bytes)",
                 host_id,
@@ -538,6 +901,10 @@ async fn handle_peer_connection(
             // Receive from peer (typed, length-prefixed)
             result = read_message(&mut recv) => {
                 match result {
+                    Ok((MSG_BYE, _)) => {
+                        info!("[iroh:{}] Peer {} closed the session", host_id, peer_id);
+                        break;
+                    }
                     Ok((msg_type, data)) => {
                         if !data.is_empty() {
                             match msg_type {
@@ -550,6 +917,10 @@ async fn handle_peer_connection(
                                 MSG_UPDATE => {
                                     info!("[iroh:{}] Received update from peer ({} bytes)", host_id, data.len());
                                     let b64 = base64::engine::general_purpose::STANDARD.encode(&data);
+                                    let forwarded = forward_to_other_peers(&peers, &own_tx, &OutboundMsg::Update(data));
+                                    if forwarded > 0 {
+                                        debug!("[iroh:{}] Forwarded update from {} to {} peer(s)", host_id, peer_id, forwarded);
+                                    }
                                     let _ = event_tx.send(IrohEvent::Update(b64));
                                     let _ = lua_handle.send();
                                 }
@@ -578,10 +949,20 @@ async fn handle_peer_connection(
             // Send to peer (typed, length-prefixed)
             msg = peer_rx.recv() => {
                 if let Some(msg) = msg {
-                    let (msg_type, data) = match msg {
-                        OutboundMsg::FullState(d) => (MSG_FULL_STATE, d),
-                        OutboundMsg::Update(d) => (MSG_UPDATE, d),
-                        OutboundMsg::Presence(d) => (MSG_PRESENCE, d),
+                    if matches!(msg, OutboundMsg::Bye) {
+                        if supports_bye(version)
+                            && let Err(e) = send_bye(&mut send).await
+                        {
+                            warn!("[iroh:{}] Failed to say bye to peer {}: {}", host_id, peer_id, e);
+                        }
+                        break;
+                    }
+                    let (msg_type, data) = match msg.into_frame() {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            error!("[iroh:{}] Not sending to peer {}: {}", host_id, peer_id, e);
+                            continue;
+                        }
                     };
                     debug!("[iroh:{}] Sending message type {} to peer ({} bytes)", host_id, msg_type, data.len());
                     if let Err(e) = write_message(&mut send, msg_type, &data).await {
@@ -600,14 +981,50 @@ async fn handle_peer_connection(
     Ok(())
 }
 
+/// Direct IP addresses of an endpoint, as strings for events and session codes.
+fn direct_addr_strings(addr: &EndpointAddr) -> Vec<String> {
+    addr.ip_addrs().map(|a| a.to_string()).collect()
+}
+
+/// Build the host's `EndpointAddr` from its endpoint ID, relay URLs and
+/// direct socket addresses.
+fn build_host_addr(
+    endpoint_id: &str,
+    relay_urls: &[String],
+    direct_addrs: &[String],
+) -> Result<EndpointAddr, String> {
+    let host_id: iroh::EndpointId = endpoint_id
+        .parse()
+        .map_err(|e| format!("Invalid endpoint ID: {}", e))?;
+
+    let mut addrs = Vec::with_capacity(relay_urls.len() + direct_addrs.len());
+    for url in relay_urls.iter().filter(|url| !url.is_empty()) {
+        let relay_url: RelayUrl = url
+            .parse()
+            .map_err(|e| format!("Invalid relay URL: {}", e))?;
+        addrs.push(TransportAddr::Relay(relay_url));
+    }
+    for addr in direct_addrs {
+        let socket_addr: std::net::SocketAddr = addr
+            .parse()
+            .map_err(|e| format!("Invalid direct address: {}", e))?;
+        addrs.push(TransportAddr::Ip(socket_addr));
+    }
+
+    Ok(EndpointAddr::from_parts(host_id, addrs))
+}
+
 /// Run the joiner (connecting) endpoint
+#[allow(clippy::too_many_arguments)]
 async fn run_joiner(
     id: Uuid,
     session_code: String,
     event_tx: UnboundedSender<IrohEvent>,
-    lua_handle: &AsyncHandle,
+    lua_handle: &EventNotifier,
     mut outbound_rx: UnboundedReceiver<OutboundMsg>,
     mut close_rx: UnboundedReceiver<()>,
+    state: SharedConnState,
+    relay_mode: RelayMode,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     info!("[iroh:{}] Starting joiner endpoint", id);
 
@@ -620,61 +1037,53 @@ async fn run_joiner(
         }
     };
 
-    // Decode session code to get host's endpoint_id and relay_url
-    let (host_endpoint_id, host_relay_url): (String, String) =
-        crate::code::decode(&session_code).map_err(|e| format!("Invalid session code: {}", e))?;
+    // Decode session code to get host's endpoint_id, relay_urls and direct addresses
+    let (host_endpoint_id, host_relay_urls, host_direct_addrs) =
+        crate::code::decode_addrs(&session_code)
+            .map_err(|e| format!("Invalid session code: {}", e))?;
 
     info!(
-        "[iroh:{}] Connecting to host: endpoint_id={}, relay_url={}",
-        id, host_endpoint_id, host_relay_url
+        "[iroh:{}] Connecting to host: endpoint_id={}, relay_urls={:?}, direct_addrs={:?}",
+        id, host_endpoint_id, host_relay_urls, host_direct_addrs
     );
 
     // Generate our own secret key
     let secret_key = SecretKey::generate(&mut rand::rng());
 
     // Build endpoint
+    let relay_disabled = matches!(relay_mode, RelayMode::Disabled);
     let endpoint = Endpoint::builder()
         .secret_key(secret_key)
-        .alpns(vec![TANDEM_ALPN.to_vec()])
-        .relay_mode(RelayMode::Default)
+        .alpns(tandem_alpns())
+        .relay_mode(relay_mode)
         .bind()
         .await?;
 
-    endpoint.online().await;
+    wait_until_reachable(&endpoint, relay_disabled).await;
+    state.lock().endpoint = Some(endpoint.clone());
 
-    let our_endpoint_id = endpoint.id().to_string();
-    let our_addr = endpoint.addr();
-    let our_relay_url = our_addr
-        .relay_urls()
-        .next()
-        .map(|u| u.to_string())
-        .unwrap_or_default();
-
-    send_event(IrohEvent::Ready {
-        endpoint_id: our_endpoint_id,
-        relay_url: our_relay_url,
-    });
-
-    // Parse host's endpoint ID
-    let host_id: iroh::EndpointId = host_endpoint_id
-        .parse()
-        .map_err(|e| format!("Invalid endpoint ID: {}", e))?;
-
-    // Parse host's relay URL
-    let relay_url: RelayUrl = host_relay_url
-        .parse()
-        .map_err(|e| format!("Invalid relay URL: {}", e))?;
+    send_event(IrohEvent::ready(&endpoint.addr()));
 
     // Build address for the host
-    let addr = EndpointAddr::from_parts(host_id, std::iter::once(TransportAddr::Relay(relay_url)));
+    let addr = build_host_addr(&host_endpoint_id, &host_relay_urls, &host_direct_addrs)?;
 
-    // Connect to host
-    let conn = endpoint.connect(addr, TANDEM_ALPN).await?;
+    // Connect to host, offering every protocol version we speak
+    let options = ConnectOptions::new().with_additional_alpns(tandem_alpns().split_off(1));
+    let conn = endpoint
+        .connect_with_opts(addr, TANDEM_ALPNS[0], options)
+        .await?
+        .await?;
     let peer_id = conn.remote_id().to_string();
+    let version = protocol_version(conn.alpn());
 
-    info!("[iroh:{}] Connected to host: {}", id, peer_id);
+    info!(
+        "[iroh:{}] Connected to host: {} (protocol v{})",
+        id, peer_id, version
+    );
+    state.lock().peers.insert(peer_id.clone());
     send_event(IrohEvent::PeerConnected {
         peer_id: peer_id.clone(),
+        protocol_version: version,
     });
 
     // Accept bidirectional stream from host
@@ -701,6 +1110,10 @@ async fn run_joiner(
             // Receive messages from host (typed, length-prefixed)
             result = read_message(&mut recv) => {
                 match result {
+                    Ok((MSG_BYE, _)) => {
+                        info!("[iroh:{}] Host closed the session", id);
+                        break;
+                    }
                     Ok((msg_type, data)) => {
                         if !data.is_empty() {
                             match msg_type {
@@ -738,10 +1151,12 @@ async fn run_joiner(
             // Send outbound messages (typed, length-prefixed)
             msg = outbound_rx.recv() => {
                 if let Some(msg) = msg {
-                    let (msg_type, data) = match msg {
-                        OutboundMsg::FullState(d) => (MSG_FULL_STATE, d),
-                        OutboundMsg::Update(d) => (MSG_UPDATE, d),
-                        OutboundMsg::Presence(d) => (MSG_PRESENCE, d),
+                    let (msg_type, data) = match msg.into_frame() {
+                        Ok(frame) => frame,
+                        Err(e) => {
+                            error!("[iroh:{}] Not sending: {}", id, e);
+                            continue;
+                        }
                     };
                     debug!("[iroh:{}] Sending message type {} to host ({} bytes)", id, msg_type, data.len());
                     if let Err(e) = write_message(&mut send, msg_type, &data).await {
@@ -754,11 +1169,17 @@ async fn run_joiner(
             // Handle close request
             _ = close_rx.recv() => {
                 info!("[iroh:{}] Close requested", id);
+                if supports_bye(version)
+                    && let Err(e) = send_bye(&mut send).await
+                {
+                    warn!("[iroh:{}] Failed to say bye to host: {}", id, e);
+                }
                 break;
             }
         }
     }
 
+    state.lock().peers.remove(&peer_id);
     send_event(IrohEvent::PeerDisconnected { peer_id });
     endpoint.close().await;
     Ok(())
@@ -768,9 +1189,59 @@ async fn run_joiner(
 // FFI Functions
 // ============================================================================
 
+/// Parse the host relay option: "" or "default" for the public n0 relays,
+/// "disabled" for LAN-only direct connections, or a custom relay URL.
+fn parse_relay_mode(relay: &str) -> Result<RelayMode, String> {
+    match relay.trim() {
+        "" | "default" => Ok(RelayMode::Default),
+        "disabled" => Ok(RelayMode::Disabled),
+        url => {
+            let url: RelayUrl = url
+                .parse()
+                .map_err(|e| format!("Invalid relay URL '{}': {}", url, e))?;
+            Ok(RelayMode::Custom(RelayMap::from(url)))
+        }
+    }
+}
+
+/// Relay mode for a joiner. An empty option uses the relays carried by the session
+/// code, or disables relays if it has none (a LAN-only host); anything else is
+/// parsed like the host option.
+fn joiner_relay_mode(relay: &str, code_relays: &[String]) -> Result<RelayMode, String> {
+    if !relay.trim().is_empty() {
+        return parse_relay_mode(relay);
+    }
+    if code_relays.is_empty() {
+        return Ok(RelayMode::Disabled);
+    }
+    let urls = code_relays
+        .iter()
+        .map(|url| {
+            url.parse::<RelayUrl>()
+                .map_err(|e| format!("Invalid relay URL '{}': {}", url, e))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(RelayMode::Custom(urls.into_iter().collect()))
+}
+
 /// Start hosting a P2P session
 /// IMPORTANT: Callbacks must be registered in _G["_TANDEM_NVIM"].iroh.callbacks[client_id] BEFORE calling
 fn iroh_host(client_id: String) -> bool {
+    start_host(client_id, false, "")
+}
+
+/// Start hosting a P2P session in polling mode (events retrieved via poll_events)
+fn iroh_host_polling(client_id: String) -> bool {
+    start_host(client_id, true, "")
+}
+
+/// Start hosting a P2P session through a custom relay, or with relays disabled
+/// (see `parse_relay_mode`). The Ready event reports the relay actually in use.
+fn iroh_host_with_relay((client_id, relay): (String, String)) -> bool {
+    start_host(client_id, false, &relay)
+}
+
+fn start_host(client_id: String, polling: bool, relay: &str) -> bool {
     let id = match Uuid::parse_str(&client_id) {
         Ok(id) => id,
         Err(e) => {
@@ -779,7 +1250,15 @@ fn iroh_host(client_id: String) -> bool {
         }
     };
 
-    match IrohClient::new_host(id) {
+    let relay_mode = match parse_relay_mode(relay) {
+        Ok(mode) => mode,
+        Err(e) => {
+            error!("[iroh:{}] {}", id, e);
+            return false;
+        }
+    };
+
+    match IrohClient::new_host(id, polling, relay_mode) {
         Ok(client) => {
             CLIENTS.lock().insert(id, client);
             info!("[iroh:{}] Host client created", id);
@@ -792,9 +1271,19 @@ fn iroh_host(client_id: String) -> bool {
     }
 }
 
-/// Join a P2P session using a session code
+/// Join a P2P session using a session code, with an optional relay option
+/// (see `joiner_relay_mode`).
 /// IMPORTANT: Callbacks must be registered BEFORE calling
-fn iroh_join((client_id, session_code): (String, String)) -> bool {
+fn iroh_join((client_id, session_code, relay): (String, String, Option<String>)) -> bool {
+    start_joiner(client_id, session_code, false, &relay.unwrap_or_default())
+}
+
+/// Join a P2P session in polling mode (events retrieved via poll_events)
+fn iroh_join_polling((client_id, session_code, relay): (String, String, Option<String>)) -> bool {
+    start_joiner(client_id, session_code, true, &relay.unwrap_or_default())
+}
+
+fn start_joiner(client_id: String, session_code: String, polling: bool, relay: &str) -> bool {
     let id = match Uuid::parse_str(&client_id) {
         Ok(id) => id,
         Err(e) => {
@@ -803,7 +1292,17 @@ fn iroh_join((client_id, session_code): (String, String)) -> bool {
         }
     };
 
-    match IrohClient::new_joiner(id, session_code) {
+    let relay_mode = match crate::code::decode_relays(&session_code)
+        .and_then(|(_, code_relays)| joiner_relay_mode(relay, &code_relays))
+    {
+        Ok(mode) => mode,
+        Err(e) => {
+            error!("[iroh:{}] {}", id, e);
+            return false;
+        }
+    };
+
+    match IrohClient::new_joiner(id, session_code, polling, relay_mode) {
         Ok(client) => {
             CLIENTS.lock().insert(id, client);
             info!("[iroh:{}] Joiner client created", id);
@@ -826,17 +1325,15 @@ fn iroh_send_full_state((client_id, data_b64): (String, String)) {
         }
     };
 
-    let data = match base64::engine::general_purpose::STANDARD.decode(&data_b64) {
-        Ok(d) => d,
-        Err(e) => {
-            error!("Invalid base64 data: {}", e);
-            return;
-        }
-    };
+    if let Err(e) = crate::crdt::check_update_size(data_b64.len()) {
+        error!("[iroh:{}] Not sending: {}", id, e);
+        return;
+    }
 
+    // Decoded in the runtime task; the FIFO outbound channel keeps sends in order
     let clients = CLIENTS.lock();
     if let Some(client) = clients.get(&id) {
-        client.send_full_state(data);
+        client.send_full_state(data_b64);
     }
 }
 
@@ -850,17 +1347,15 @@ fn iroh_send_update((client_id, data_b64): (String, String)) {
         }
     };
 
-    let data = match base64::engine::general_purpose::STANDARD.decode(&data_b64) {
-        Ok(d) => d,
-        Err(e) => {
-            error!("Invalid base64 data: {}", e);
-            return;
-        }
-    };
+    if let Err(e) = crate::crdt::check_update_size(data_b64.len()) {
+        error!("[iroh:{}] Not sending: {}", id, e);
+        return;
+    }
 
+    // Decoded in the runtime task; the FIFO outbound channel keeps sends in order
     let clients = CLIENTS.lock();
     if let Some(client) = clients.get(&id) {
-        client.send_update(data);
+        client.send_update(data_b64);
     }
 }
 
@@ -896,6 +1391,70 @@ fn iroh_close(client_id: String) {
     }
 }
 
+/// Set the maximum payload size in bytes of a frame read from a peer, overriding
+/// the default derived from the Lua payload limit (see crdt_set_max_update_size).
+/// Peers announcing a larger frame are disconnected. 0 disables the limit.
+fn iroh_set_max_frame_size(max_bytes: usize) {
+    MAX_FRAME_LEN.store(max_bytes, Ordering::Relaxed);
+    info!("Max frame size set to {} bytes", max_bytes);
+}
+
+/// Close every active client and clear the registry.
+/// Intended for VimLeave / plugin reload; safe to call when no clients exist.
+/// Returns the number of clients closed.
+fn iroh_close_all() -> usize {
+    let clients: Vec<IrohClient> = CLIENTS.lock().drain().map(|(_, client)| client).collect();
+
+    let mut queues = POLLED_EVENTS.lock();
+    for client in &clients {
+        client.close();
+        queues.remove(&client.id);
+    }
+
+    if !clients.is_empty() {
+        info!("[iroh] Closed {} client(s)", clients.len());
+    }
+    clients.len()
+}
+
+/// Drain queued events for a client created in polling mode.
+/// Returns list of events as JSON strings, e.g. {"type":"update","data":"..."}
+fn iroh_poll_events(client_id: String) -> Vec<String> {
+    let id = match Uuid::parse_str(&client_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid client ID '{}': {}", client_id, e);
+            return Vec::new();
+        }
+    };
+
+    let mut queues = POLLED_EVENTS.lock();
+    let Some(rx) = queues.get_mut(&id) else {
+        return Vec::new();
+    };
+
+    let mut events = Vec::new();
+    loop {
+        match rx.try_recv() {
+            Ok(event) => events.push(event.to_json()),
+            Err(mpsc::error::TryRecvError::Empty) => break,
+            Err(mpsc::error::TryRecvError::Disconnected) => {
+                // Client task has finished and everything has been drained
+                queues.remove(&id);
+                break;
+            }
+        }
+    }
+    events
+}
+
+/// IDs of every registered client as a JSON array, for finding orphaned clients.
+fn iroh_list_clients() -> String {
+    let mut ids: Vec<String> = CLIENTS.lock().keys().map(Uuid::to_string).collect();
+    ids.sort();
+    serde_json::to_string(&ids).unwrap_or_else(|_| "[]".to_string())
+}
+
 /// Check if a client exists
 fn iroh_is_connected(client_id: String) -> bool {
     let id = match Uuid::parse_str(&client_id) {
@@ -906,6 +1465,29 @@ fn iroh_is_connected(client_id: String) -> bool {
     CLIENTS.lock().contains_key(&id)
 }
 
+/// Connection stats for a client as JSON:
+/// `{"endpoint_id":..., "peer_count":N, "peers":[{"peer_id":..., "path":"direct|relay|mixed|none"}]}`
+fn iroh_stats(client_id: String) -> String {
+    let id = match Uuid::parse_str(&client_id) {
+        Ok(id) => id,
+        Err(e) => {
+            warn!("Invalid client ID '{}': {}", client_id, e);
+            return String::new();
+        }
+    };
+
+    // Clone the state handle so path lookups don't hold the registry lock
+    let state = match CLIENTS.lock().get(&id) {
+        Some(client) => client.state.clone(),
+        None => {
+            warn!("[iroh:{}] Client not found", id);
+            return String::new();
+        }
+    };
+
+    state.lock().to_json()
+}
+
 /// Generate a new UUID for a client
 fn iroh_generate_client_id() -> String {
     Uuid::new_v4().to_string()
@@ -927,11 +1509,35 @@ pub fn iroh_ffi() -> Dictionary {
             )),
         ),
         (
-            "join",
+            "host_with_relay",
             Object::from(Function::<(String, String), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(iroh_host_with_relay(args)) },
+            )),
+        ),
+        (
+            "join",
+            Object::from(Function::<(String, String, Option<String>), bool>::from_fn(
                 |args| -> Result<bool, nvim_oxi::Error> { Ok(iroh_join(args)) },
             )),
         ),
+        (
+            "host_polling",
+            Object::from(Function::<String, bool>::from_fn(
+                |id| -> Result<bool, nvim_oxi::Error> { Ok(iroh_host_polling(id)) },
+            )),
+        ),
+        (
+            "join_polling",
+            Object::from(Function::<(String, String, Option<String>), bool>::from_fn(
+                |args| -> Result<bool, nvim_oxi::Error> { Ok(iroh_join_polling(args)) },
+            )),
+        ),
+        (
+            "poll_events",
+            Object::from(Function::<String, Vec<String>>::from_fn(
+                |id| -> Result<Vec<String>, nvim_oxi::Error> { Ok(iroh_poll_events(id)) },
+            )),
+        ),
         (
             "send_full_state",
             Object::from(Function::<(String, String), ()>::from_fn(
@@ -968,6 +1574,33 @@ pub fn iroh_ffi() -> Dictionary {
                 },
             )),
         ),
+        (
+            "set_max_frame_size",
+            Object::from(Function::<usize, ()>::from_fn(
+                |max_bytes| -> Result<(), nvim_oxi::Error> {
+                    iroh_set_max_frame_size(max_bytes);
+                    Ok(())
+                },
+            )),
+        ),
+        (
+            "stats",
+            Object::from(Function::<String, String>::from_fn(
+                |id| -> Result<String, nvim_oxi::Error> { Ok(iroh_stats(id)) },
+            )),
+        ),
+        (
+            "list_clients",
+            Object::from(Function::<(), String>::from_fn(
+                |_| -> Result<String, nvim_oxi::Error> { Ok(iroh_list_clients()) },
+            )),
+        ),
+        (
+            "close_all",
+            Object::from(Function::<(), usize>::from_fn(
+                |_| -> Result<usize, nvim_oxi::Error> { Ok(iroh_close_all()) },
+            )),
+        ),
         (
             "is_connected",
             Object::from(Function::<String, bool>::from_fn(
@@ -976,3 +1609,574 @@ pub fn iroh_ffi() -> Dictionary {
         ),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_event_json() {
+        let ready = IrohEvent::Ready {
+            endpoint_id: "abc".to_string(),
+            relay_url: "https://relay.example.com".to_string(),
+            direct_addrs: vec!["192.168.1.10:4433".to_string()],
+        };
+        let json: serde_json::Value = serde_json::from_str(&ready.to_json()).unwrap();
+        assert_eq!(json["type"], "ready");
+        assert_eq!(json["endpoint_id"], "abc");
+        assert_eq!(json["relay_url"], "https://relay.example.com");
+
+        let presence = IrohEvent::Presence {
+            peer_id: "peer".to_string(),
+            data: r#"{"row":1}"#.to_string(),
+        };
+        let json: serde_json::Value = serde_json::from_str(&presence.to_json()).unwrap();
+        assert_eq!(json["type"], "presence");
+        assert_eq!(json["data"], r#"{"row":1}"#);
+    }
+
+    #[tokio::test]
+    async fn test_bye_frame_roundtrip() {
+        let (msg_type, data) = OutboundMsg::Bye.into_frame().unwrap();
+        assert_eq!(msg_type, MSG_BYE);
+        assert!(data.is_empty());
+
+        let mut wire = Vec::new();
+        write_message(&mut wire, msg_type, &data).await.unwrap();
+        write_message(&mut wire, MSG_UPDATE, b"abc").await.unwrap();
+        assert_eq!(wire[..5], [MSG_BYE, 0, 0, 0, 0]);
+
+        let mut recv = wire.as_slice();
+        assert_eq!(
+            read_message(&mut recv).await.unwrap(),
+            (MSG_BYE, Vec::new())
+        );
+        assert_eq!(
+            read_message(&mut recv).await.unwrap(),
+            (MSG_UPDATE, b"abc".to_vec())
+        );
+        assert!(read_message(&mut recv).await.is_err());
+    }
+
+    // Tests run outside Neovim, which is what provides libuv. The endpoint tests
+    // only use EventNotifier(None), so this is never called, but it must link.
+    #[unsafe(no_mangle)]
+    extern "C" fn uv_async_send(_handle: *mut std::ffi::c_void) -> std::ffi::c_int {
+        0
+    }
+
+    /// Skip events until one matches `pred`, returning it with the events skipped
+    /// on the way. Fails the test if nothing matches within a few seconds.
+    async fn wait_for_event(
+        rx: &mut UnboundedReceiver<IrohEvent>,
+        pred: impl Fn(&IrohEvent) -> bool,
+    ) -> (IrohEvent, Vec<IrohEvent>) {
+        let mut skipped = Vec::new();
+        loop {
+            let event = tokio::time::timeout(std::time::Duration::from_secs(10), rx.recv())
+                .await
+                .expect("timed out waiting for event")
+                .expect("event channel closed");
+            if pred(&event) {
+                return (event, skipped);
+            }
+            skipped.push(event);
+        }
+    }
+
+    /// Channels and state of an endpoint task started by a test
+    struct TestEndpoint {
+        events: UnboundedReceiver<IrohEvent>,
+        outbound: UnboundedSender<OutboundMsg>,
+        close: UnboundedSender<()>,
+        state: SharedConnState,
+    }
+
+    /// Run a relay-less host, returning it once Ready with a loopback session code
+    async fn spawn_test_host() -> (
+        TestEndpoint,
+        String,
+        tokio::task::JoinHandle<Result<(), Box<dyn std::error::Error + Send + Sync>>>,
+    ) {
+        let (event_tx, mut events) = mpsc::unbounded_channel();
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let (close_tx, close_rx) = mpsc::unbounded_channel();
+        let state = SharedConnState::default();
+        let task_state = state.clone();
+        let task = tokio::spawn(async move {
+            let notifier = EventNotifier(None);
+            run_host(
+                Uuid::new_v4(),
+                event_tx,
+                &notifier,
+                outbound_rx,
+                close_rx,
+                task_state,
+                RelayMode::Disabled,
+            )
+            .await
+        });
+
+        let (ready, _) =
+            wait_for_event(&mut events, |e| matches!(e, IrohEvent::Ready { .. })).await;
+        let IrohEvent::Ready { endpoint_id, .. } = ready else {
+            unreachable!()
+        };
+        let port = state
+            .lock()
+            .endpoint
+            .as_ref()
+            .expect("host endpoint")
+            .bound_sockets()
+            .iter()
+            .find(|addr| addr.is_ipv4())
+            .expect("IPv4 socket")
+            .port();
+        let code = crate::code::encode_with_addrs(
+            &endpoint_id,
+            &[] as &[&str],
+            &[format!("127.0.0.1:{port}")],
+        )
+        .expect("session code");
+
+        let host = TestEndpoint {
+            events,
+            outbound: outbound_tx,
+            close: close_tx,
+            state,
+        };
+        (host, code, task)
+    }
+
+    /// Run a relay-less joiner for `code` and wait until it received the host's
+    /// initial state, which the host answers as Lua would on peer_connected
+    async fn connect_test_joiner(host: &mut TestEndpoint, code: &str) -> TestEndpoint {
+        let (event_tx, mut events) = mpsc::unbounded_channel();
+        let (outbound_tx, outbound_rx) = mpsc::unbounded_channel();
+        let (close_tx, close_rx) = mpsc::unbounded_channel();
+        let state = SharedConnState::default();
+        let task_state = state.clone();
+        let code = code.to_string();
+        tokio::spawn(async move {
+            let notifier = EventNotifier(None);
+            if let Err(e) = run_joiner(
+                Uuid::new_v4(),
+                code,
+                event_tx,
+                &notifier,
+                outbound_rx,
+                close_rx,
+                task_state,
+                RelayMode::Disabled,
+            )
+            .await
+            {
+                error!("test joiner failed: {}", e);
+            }
+        });
+
+        wait_for_event(&mut host.events, |e| {
+            matches!(e, IrohEvent::PeerConnected { .. })
+        })
+        .await;
+        host.outbound
+            .send(OutboundMsg::FullStateB64("c3RhdGU=".to_string()))
+            .unwrap();
+        wait_for_event(&mut events, |e| matches!(e, IrohEvent::FullState(_))).await;
+
+        TestEndpoint {
+            events,
+            outbound: outbound_tx,
+            close: close_tx,
+            state,
+        }
+    }
+
+    /// Wait until the number of peers in `state` is `expected`
+    async fn wait_for_peer_count(state: &SharedConnState, expected: usize) {
+        let deadline = tokio::time::Instant::now() + std::time::Duration::from_secs(10);
+        while state.lock().peers.len() != expected {
+            assert!(
+                tokio::time::Instant::now() < deadline,
+                "peer count stuck at {}, expected {}",
+                state.lock().peers.len(),
+                expected
+            );
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_host_relays_updates_between_joiners() {
+        let (mut host, code, host_task) = spawn_test_host().await;
+        let mut a = connect_test_joiner(&mut host, &code).await;
+        let mut b = connect_test_joiner(&mut host, &code).await;
+
+        let expected = base64::engine::general_purpose::STANDARD.encode(b"from-a");
+        a.outbound
+            .send(OutboundMsg::UpdateB64(expected.clone()))
+            .unwrap();
+
+        let (update, _) =
+            wait_for_event(&mut b.events, |e| matches!(e, IrohEvent::Update(_))).await;
+        assert!(matches!(update, IrohEvent::Update(ref data) if *data == expected));
+        let (update, _) =
+            wait_for_event(&mut host.events, |e| matches!(e, IrohEvent::Update(_))).await;
+        assert!(matches!(update, IrohEvent::Update(ref data) if *data == expected));
+
+        // A marker broadcast now is queued behind any echo of the update to A
+        host.outbound
+            .send(OutboundMsg::Presence(b"{}".to_vec()))
+            .unwrap();
+        let (_, skipped) =
+            wait_for_event(&mut a.events, |e| matches!(e, IrohEvent::Presence { .. })).await;
+        assert!(!skipped.iter().any(|e| matches!(e, IrohEvent::Update(_))));
+
+        for close in [a.close, b.close, host.close] {
+            let _ = close.send(());
+        }
+        tokio::time::timeout(std::time::Duration::from_secs(10), host_task)
+            .await
+            .expect("host did not shut down")
+            .expect("host task panicked")
+            .expect("host failed");
+    }
+
+    #[test]
+    fn test_base64_payloads_decoded_for_the_wire() {
+        let msg = OutboundMsg::UpdateB64("AQID".to_string());
+        assert!(matches!(msg.clone().decoded(), Ok(OutboundMsg::Update(d)) if d == [1, 2, 3]));
+        assert_eq!(msg.into_frame(), Ok((MSG_UPDATE, vec![1, 2, 3])));
+
+        let msg = OutboundMsg::FullStateB64("AQID".to_string());
+        assert_eq!(msg.into_frame(), Ok((MSG_FULL_STATE, vec![1, 2, 3])));
+
+        assert!(
+            OutboundMsg::UpdateB64("not base64!".to_string())
+                .decoded()
+                .is_err()
+        );
+        assert!(matches!(
+            OutboundMsg::Presence(b"{}".to_vec()).decoded(),
+            Ok(OutboundMsg::Presence(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_message_across_small_reads() {
+        // A tiny pipe forces the payload to arrive in many short reads
+        let (mut writer, mut reader) = tokio::io::duplex(7);
+        let payload: Vec<u8> = (0..=255u8).cycle().take(1000).collect();
+        let expected = payload.clone();
+        tokio::spawn(async move {
+            write_message(&mut writer, MSG_UPDATE, &payload)
+                .await
+                .unwrap();
+        });
+
+        assert_eq!(
+            read_message(&mut reader).await.unwrap(),
+            (MSG_UPDATE, expected)
+        );
+        // The writer is gone, so a new frame can't start
+        assert!(read_message(&mut reader).await.is_err());
+    }
+
+    #[test]
+    fn test_frame_len_limit() {
+        assert_eq!(frame_len(5u32.to_be_bytes()), Ok(5));
+
+        // The default frame limit is the largest payload Lua accepts as base64
+        let b64_max = 64 * 1024 * 1024;
+        let raw_max = raw_len_for_b64(b64_max);
+        assert_eq!(raw_max, 48 * 1024 * 1024);
+        let encoded = base64::engine::general_purpose::STANDARD.encode(vec![0u8; raw_max]);
+        assert!(encoded.len() <= b64_max);
+        assert_eq!(raw_len_for_b64(0), 0);
+
+        let err = frame_len(u32::MAX.to_be_bytes()).unwrap_err();
+        assert!(err.contains("exceeds"), "{}", err);
+    }
+
+    #[test]
+    fn test_alpn_list() {
+        assert_eq!(
+            tandem_alpns(),
+            vec![b"tandem/crdt/2".to_vec(), b"tandem/crdt/1".to_vec()]
+        );
+        assert_eq!(protocol_version(TANDEM_ALPNS[0]), 2);
+        assert_eq!(protocol_version(b"tandem/crdt/1"), 1);
+        assert_eq!(protocol_version(b"other/1"), 0);
+
+        // The bye frame is a v2 addition
+        assert!(supports_bye(protocol_version(TANDEM_ALPNS[0])));
+        assert!(!supports_bye(protocol_version(b"tandem/crdt/1")));
+
+        let event = IrohEvent::PeerConnected {
+            peer_id: "peer".to_string(),
+            protocol_version: 2,
+        };
+        let json: serde_json::Value = serde_json::from_str(&event.to_json()).unwrap();
+        assert_eq!(json["protocol_version"], 2);
+    }
+
+    #[test]
+    fn test_poll_events_drains_queue() {
+        let id = Uuid::new_v4();
+        let (tx, rx) = mpsc::unbounded_channel::<IrohEvent>();
+        POLLED_EVENTS.lock().insert(id, rx);
+
+        tx.send(IrohEvent::Update("AAEC".to_string())).unwrap();
+        tx.send(IrohEvent::PeerDisconnected {
+            peer_id: "peer".to_string(),
+        })
+        .unwrap();
+
+        let events = iroh_poll_events(id.to_string());
+        assert_eq!(events.len(), 2);
+        assert!(events[0].contains(r#""type":"update""#));
+        assert!(events[1].contains(r#""type":"peer_disconnected""#));
+        assert!(iroh_poll_events(id.to_string()).is_empty());
+
+        // Once the client task is gone the queue is dropped after draining
+        tx.send(IrohEvent::Error("closed".to_string())).unwrap();
+        drop(tx);
+        assert_eq!(iroh_poll_events(id.to_string()).len(), 1);
+        assert!(!POLLED_EVENTS.lock().contains_key(&id));
+    }
+
+    #[test]
+    fn test_close_all() {
+        let id = Uuid::new_v4();
+        let (outbound_tx, _outbound_rx) = mpsc::unbounded_channel::<OutboundMsg>();
+        let (close_tx, mut close_rx) = mpsc::unbounded_channel::<()>();
+        let (_event_tx, event_rx) = mpsc::unbounded_channel::<IrohEvent>();
+        CLIENTS.lock().insert(
+            id,
+            IrohClient {
+                id,
+                outbound_tx,
+                close_tx,
+                state: SharedConnState::default(),
+                _notifier: EventNotifier(None),
+            },
+        );
+        POLLED_EVENTS.lock().insert(id, event_rx);
+
+        assert_eq!(iroh_close_all(), 1);
+        assert!(close_rx.try_recv().is_ok());
+        assert!(!iroh_is_connected(id.to_string()));
+        assert!(!POLLED_EVENTS.lock().contains_key(&id));
+
+        // Safe to call again with an empty registry
+        assert_eq!(iroh_close_all(), 0);
+    }
+
+    #[test]
+    fn test_build_host_addr_multiple_relays() {
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public().to_string();
+        let relays = vec![
+            "https://relay-a.example.com".to_string(),
+            "https://relay-b.example.com".to_string(),
+        ];
+
+        let addr = build_host_addr(&endpoint_id, &relays, &[]).expect("build");
+
+        assert_eq!(addr.id.to_string(), endpoint_id);
+        let urls: Vec<String> = addr.relay_urls().map(|u| u.to_string()).collect();
+        assert_eq!(urls.len(), 2);
+        assert!(
+            urls.iter()
+                .any(|u| u.starts_with("https://relay-a.example.com"))
+        );
+        assert!(
+            urls.iter()
+                .any(|u| u.starts_with("https://relay-b.example.com"))
+        );
+
+        assert!(build_host_addr("not-an-id", &relays, &[]).is_err());
+    }
+
+    #[test]
+    fn test_build_host_addr_relay_and_direct() {
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public().to_string();
+        let relays = vec!["https://relay.example.com".to_string()];
+        let direct = vec![
+            "192.168.1.10:4433".to_string(),
+            "[fe80::1]:4433".to_string(),
+        ];
+
+        let addr = build_host_addr(&endpoint_id, &relays, &direct).expect("build");
+
+        assert_eq!(addr.relay_urls().count(), 1);
+        let ips: Vec<String> = addr.ip_addrs().map(|a| a.to_string()).collect();
+        assert_eq!(ips.len(), 2);
+        assert!(ips.contains(&"192.168.1.10:4433".to_string()));
+        assert!(ips.contains(&"[fe80::1]:4433".to_string()));
+
+        let bad = vec!["nope".to_string()];
+        assert!(build_host_addr(&endpoint_id, &relays, &bad).is_err());
+    }
+
+    #[test]
+    fn test_ready_includes_direct_addrs() {
+        let endpoint_id = SecretKey::generate(&mut rand::rng()).public().to_string();
+        let relays = vec!["https://relay.example.com".to_string()];
+        let direct = vec!["192.168.1.10:4433".to_string()];
+
+        let addr = build_host_addr(&endpoint_id, &relays, &direct).expect("build");
+        let json: serde_json::Value =
+            serde_json::from_str(&IrohEvent::ready(&addr).to_json()).unwrap();
+        assert_eq!(json["type"], "ready");
+        assert_eq!(json["endpoint_id"], endpoint_id);
+        let relay: RelayUrl = relays[0].parse().unwrap();
+        assert_eq!(json["relay_url"], relay.to_string());
+        assert_eq!(
+            json["direct_addrs"],
+            serde_json::json!(["192.168.1.10:4433"])
+        );
+
+        // Relay-less hosts still report their direct addresses
+        let addr = build_host_addr(&endpoint_id, &[], &direct).expect("build");
+        let IrohEvent::Ready {
+            relay_url,
+            direct_addrs,
+            ..
+        } = IrohEvent::ready(&addr)
+        else {
+            panic!("expected Ready");
+        };
+        assert!(relay_url.is_empty());
+        assert_eq!(direct_addrs, direct);
+    }
+
+    #[test]
+    fn test_forward_to_other_peers_skips_sender() {
+        let peers: Mutex<HashMap<String, UnboundedSender<OutboundMsg>>> =
+            Mutex::new(HashMap::new());
+        let (tx_a, mut rx_a) = mpsc::unbounded_channel::<OutboundMsg>();
+        let (tx_b, mut rx_b) = mpsc::unbounded_channel::<OutboundMsg>();
+        let (tx_c, mut rx_c) = mpsc::unbounded_channel::<OutboundMsg>();
+        peers.lock().insert("peer-a".to_string(), tx_a.clone());
+        // A peer whose id is not known yet is still keyed by its pending_ key
+        peers.lock().insert("pending_b".to_string(), tx_b);
+        peers.lock().insert("peer-c".to_string(), tx_c);
+
+        // Joiner A's edit reaches B and C, but is not echoed back to A
+        let forwarded = forward_to_other_peers(&peers, &tx_a, &OutboundMsg::Update(vec![1, 2, 3]));
+        assert_eq!(forwarded, 2);
+
+        assert!(rx_a.try_recv().is_err());
+        for rx in [&mut rx_b, &mut rx_c] {
+            match rx.try_recv() {
+                Ok(OutboundMsg::Update(data)) => assert_eq!(data, vec![1, 2, 3]),
+                other => panic!("expected forwarded update, got {:?}", other),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_stats_peer_count() {
+        let (mut host, code, host_task) = spawn_test_host().await;
+        // What iroh_stats reports for the host, kept out of the global registry
+        // so test_close_all can't close it mid-test
+        let host_state = host.state.clone();
+        let stats =
+            || -> serde_json::Value { serde_json::from_str(&host_state.lock().to_json()).unwrap() };
+        let peer_count = || stats()["peer_count"].clone();
+        assert_eq!(peer_count(), 0);
+
+        let a = connect_test_joiner(&mut host, &code).await;
+        wait_for_peer_count(&host.state, 1).await;
+        assert_eq!(a.state.lock().peers.len(), 1);
+        let b = connect_test_joiner(&mut host, &code).await;
+        wait_for_peer_count(&host.state, 2).await;
+        assert_eq!(peer_count(), 2);
+
+        let joiner_ids: BTreeSet<String> = [&a, &b]
+            .iter()
+            .map(|joiner| match &joiner.state.lock().endpoint {
+                Some(endpoint) => endpoint.id().to_string(),
+                None => panic!("joiner endpoint not bound"),
+            })
+            .collect();
+        let reported: BTreeSet<String> = stats()["peers"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|peer| peer["peer_id"].as_str().unwrap().to_string())
+            .collect();
+        assert_eq!(reported, joiner_ids);
+
+        // Disconnects are tracked on both sides
+        let _ = a.close.send(());
+        wait_for_peer_count(&host.state, 1).await;
+        wait_for_peer_count(&a.state, 0).await;
+        let _ = b.close.send(());
+        wait_for_peer_count(&host.state, 0).await;
+        assert_eq!(peer_count(), 0);
+
+        let _ = host.close.send(());
+        tokio::time::timeout(std::time::Duration::from_secs(10), host_task)
+            .await
+            .expect("host did not shut down")
+            .expect("host task panicked")
+            .expect("host failed");
+    }
+
+    #[test]
+    fn test_parse_relay_mode() {
+        assert!(matches!(parse_relay_mode(""), Ok(RelayMode::Default)));
+        assert!(matches!(
+            parse_relay_mode("default"),
+            Ok(RelayMode::Default)
+        ));
+        assert!(matches!(
+            parse_relay_mode("disabled"),
+            Ok(RelayMode::Disabled)
+        ));
+
+        let custom = parse_relay_mode("https://relay.example.com").expect("custom");
+        let expected: RelayUrl = "https://relay.example.com".parse().unwrap();
+        match custom {
+            RelayMode::Custom(map) => {
+                assert_eq!(map.len(), 1);
+                assert!(map.contains(&expected));
+            }
+            other => panic!("expected custom relay mode, got {:?}", other),
+        }
+
+        assert!(parse_relay_mode("not a url").is_err());
+    }
+
+    #[test]
+    fn test_joiner_relay_mode() {
+        // A code without relays comes from a LAN-only host
+        assert!(matches!(
+            joiner_relay_mode("", &[]),
+            Ok(RelayMode::Disabled)
+        ));
+
+        // Otherwise the joiner uses the host's relays, not the public defaults
+        let code_relays = vec!["https://relay.example.com".to_string()];
+        let expected: RelayUrl = "https://relay.example.com".parse().unwrap();
+        match joiner_relay_mode("", &code_relays).expect("code relays") {
+            RelayMode::Custom(map) => {
+                assert_eq!(map.len(), 1);
+                assert!(map.contains(&expected));
+            }
+            other => panic!("expected custom relay mode, got {:?}", other),
+        }
+
+        // An explicit option overrides the code
+        assert!(matches!(
+            joiner_relay_mode("disabled", &code_relays),
+            Ok(RelayMode::Disabled)
+        ));
+        assert!(matches!(
+            joiner_relay_mode("default", &[]),
+            Ok(RelayMode::Default)
+        ));
+        assert!(joiner_relay_mode("", &["not a url".to_string()]).is_err());
+    }
+}
@@ -1,12 +1,17 @@
-use log::{self, info};
+use log::{self, LevelFilter, info, warn};
 use log4rs::{
+    Handle,
     append::file::FileAppender,
     config::{Appender, Config, Root},
     encode::pattern::PatternEncoder,
 };
-use nvim_oxi::Dictionary;
+use nvim_oxi::{Dictionary, Function};
 use parking_lot::Mutex;
-use std::sync::OnceLock;
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::OnceLock,
+};
 use tokio::runtime::Runtime;
 
 mod auth;
@@ -22,40 +27,107 @@ pub fn runtime() -> &'static Runtime {
     ASYNC_RUNTIME.get_or_init(|| Runtime::new().expect("Failed to create async runtime"))
 }
 
-/// Logger initialization guard
-static LOGGER_INIT: OnceLock<Mutex<()>> = OnceLock::new();
+/// Handle to the installed logger, used to swap its config at runtime
+static LOGGER: OnceLock<Mutex<Option<Handle>>> = OnceLock::new();
+
+/// Level used when neither `set_log_config` nor `TANDEM_LOG_LEVEL` gives a valid one
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Parse a level name ("debug", "WARN", ...), falling back to Info.
+fn parse_level(level: &str) -> LevelFilter {
+    LevelFilter::from_str(level.trim()).unwrap_or(DEFAULT_LOG_LEVEL)
+}
+
+/// Log file path from `TANDEM_LOG_PATH`, else `tandem-nvim.log` in the temp dir.
+fn default_log_path() -> PathBuf {
+    std::env::var_os("TANDEM_LOG_PATH")
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .unwrap_or_else(|| std::env::temp_dir().join("tandem-nvim.log"))
+}
+
+/// Log level from `TANDEM_LOG_LEVEL`, else Info.
+fn default_log_level() -> LevelFilter {
+    std::env::var("TANDEM_LOG_LEVEL")
+        .map(|level| parse_level(&level))
+        .unwrap_or(DEFAULT_LOG_LEVEL)
+}
+
+fn build_log_config(path: &Path, level: LevelFilter) -> Result<Config, String> {
+    let file_appender = FileAppender::builder()
+        .encoder(Box::new(PatternEncoder::new(
+            "[{l}] {d(%Y-%m-%d %H:%M:%S)} {f}:{L} - {m}\n",
+        )))
+        .build(path)
+        .map_err(|e| format!("Failed to create file appender: {e}"))?;
+
+    Config::builder()
+        .appender(Appender::builder().build("file", Box::new(file_appender)))
+        .build(Root::builder().appender("file").build(level))
+        .map_err(|e| format!("Failed to create log config: {e}"))
+}
 
 fn init_logger() {
-    let _guard = LOGGER_INIT.get_or_init(|| {
-        let file_appender = FileAppender::builder()
-            .encoder(Box::new(PatternEncoder::new(
-                "[{l}] {d(%Y-%m-%d %H:%M:%S)} {f}:{L} - {m}\n",
-            )))
-            .build("/tmp/tandem-nvim.log")
-            .expect("Failed to create file appender");
-
-        let log_config = Config::builder()
-            .appender(Appender::builder().build("file", Box::new(file_appender)))
-            .build(
-                Root::builder()
-                    .appender("file")
-                    .build(log::LevelFilter::Debug),
-            )
-            .expect("Failed to create log config");
-
-        let _ = log4rs::init_config(log_config);
+    LOGGER.get_or_init(|| {
+        let handle = build_log_config(&default_log_path(), default_log_level())
+            .ok()
+            .and_then(|config| log4rs::init_config(config).ok());
         log_panics::init();
 
-        Mutex::new(())
+        Mutex::new(handle)
     });
 }
 
+/// Point the logger at a new file and level.
+///
+/// An empty `path` or `level` falls back to `TANDEM_LOG_PATH` /
+/// `TANDEM_LOG_LEVEL`, then to the defaults. Unknown levels map to Info.
+fn set_log_config(path: &str, level: &str) -> Result<(), String> {
+    let path = if path.is_empty() {
+        default_log_path()
+    } else {
+        PathBuf::from(path)
+    };
+    let level = if level.is_empty() {
+        default_log_level()
+    } else {
+        parse_level(level)
+    };
+    let config = build_log_config(&path, level)?;
+
+    init_logger();
+    let mut guard = LOGGER.get().expect("logger initialized").lock();
+    match guard.as_ref() {
+        Some(handle) => handle.set_config(config),
+        None => {
+            *guard = Some(
+                log4rs::init_config(config).map_err(|e| format!("Failed to set logger: {e}"))?,
+            );
+        }
+    }
+
+    info!("Logging to {} at level {}", path.display(), level);
+    Ok(())
+}
+
 #[nvim_oxi::plugin]
 fn tandem_ffi() -> nvim_oxi::Result<Dictionary> {
     init_logger();
     info!("tandem_ffi plugin loaded");
 
     let api = Dictionary::from_iter([
+        (
+            "set_log_config",
+            nvim_oxi::Object::from(Function::<(String, String), bool>::from_fn(
+                |(path, level)| match set_log_config(&path, &level) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        warn!("set_log_config failed: {}", e);
+                        false
+                    }
+                },
+            )),
+        ),
         ("auth", nvim_oxi::Object::from(auth::auth_ffi())),
         ("code", nvim_oxi::Object::from(code::code_ffi())),
         ("crdt", nvim_oxi::Object::from(crdt::crdt_ffi())),
@@ -65,3 +137,33 @@ fn tandem_ffi() -> nvim_oxi::Result<Dictionary> {
 
     Ok(api)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_level() {
+        assert_eq!(parse_level("debug"), LevelFilter::Debug);
+        assert_eq!(parse_level("WARN"), LevelFilter::Warn);
+        assert_eq!(parse_level(" trace "), LevelFilter::Trace);
+        assert_eq!(parse_level("off"), LevelFilter::Off);
+    }
+
+    #[test]
+    fn test_parse_level_invalid_falls_back_to_info() {
+        assert_eq!(parse_level("verbose"), LevelFilter::Info);
+        assert_eq!(parse_level(""), LevelFilter::Info);
+    }
+
+    #[test]
+    fn test_build_log_config_custom_path() {
+        let path = std::env::temp_dir().join(format!("tandem-test-{}.log", uuid::Uuid::new_v4()));
+
+        let config = build_log_config(&path, LevelFilter::Warn).expect("config");
+        assert_eq!(config.root().level(), LevelFilter::Warn);
+        assert!(path.exists());
+
+        let _ = std::fs::remove_file(path);
+    }
+}